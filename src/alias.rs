@@ -0,0 +1,506 @@
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_ecr as ecr;
+use aws_sdk_sts as sts;
+use log::{error, info};
+
+use crate::args::{AliasCommand, AliasSetArgs, RollbackArgs};
+use crate::aws_utils::{
+    add_image_tag, create_ecr_repo_if_needed, ecr_error, ecr_image_digest, fetch_aws_account_id,
+    load_aws_config, ECR_REPO_PREFIX,
+};
+use crate::build::{
+    format_bearer_token, format_table, lookup_current_build, ClusterBuild, DEFAULT_CPU_COUNT,
+    DEFAULT_MEMORY_MB,
+};
+use crate::config::{
+    load_e2b_toml, read_user_config, resolve_aws_region, ClusterSettings, E2bConfigToml,
+    E2bSection, ReplicaSection, UserConfig,
+};
+use crate::docker_utils::{pull_docker_image, push_image, tag_image};
+use crate::e2b_api::{build_template, list_templates, TemplateInfo};
+use crate::ecr_auth::EcrAuthProvider;
+use crate::secrets::resolve_secret;
+
+/// An e2b cluster and the token to call it with
+struct Cluster {
+    e2b_domain: String,
+    access_token: String,
+}
+
+impl Cluster {
+    /// Cluster of the active profile, resolved like `template build` resolves it
+    async fn primary() -> Result<Self> {
+        let settings = ClusterSettings::read()?;
+        let e2b_domain = settings.require_e2b_domain()?;
+        let raw_access_token = settings.require_e2b_access_token()?;
+        let aws_region = settings.require_aws_region().ok();
+        let access_token =
+            format_bearer_token(&resolve_secret(&raw_access_token, aws_region.as_deref()).await?);
+        Ok(Self {
+            e2b_domain,
+            access_token,
+        })
+    }
+
+    /// A `[[replicas]]` cluster, which uses the primary token unless it has its own
+    async fn replica(replica: &ReplicaSection, primary: &Cluster) -> Result<Self> {
+        let access_token = match replica.e2b_access_token.as_deref() {
+            Some(raw) => {
+                format_bearer_token(&resolve_secret(raw, Some(&replica.aws_region)).await?)
+            }
+            None => primary.access_token.clone(),
+        };
+        Ok(Self {
+            e2b_domain: replica.e2b_domain.clone(),
+            access_token,
+        })
+    }
+}
+
+/// The ECR registry of one region
+struct Registry {
+    aws_region: String,
+    account_id: String,
+    ecr_client: ecr::Client,
+    ecr_auth: EcrAuthProvider,
+}
+
+impl Registry {
+    async fn connect(aws_region: &str, user_cfg: Option<&UserConfig>) -> Result<Self> {
+        let shared_config = load_aws_config(aws_region).await;
+        let ecr_client = ecr::Client::new(&shared_config);
+        let account_id = fetch_aws_account_id(&sts::Client::new(&shared_config)).await?;
+        let use_token_cache = user_cfg
+            .and_then(|c| c.aws.as_ref().and_then(|a| a.ecr_token_cache))
+            .unwrap_or(false);
+        let ecr_auth =
+            EcrAuthProvider::new(ecr_client.clone(), &account_id, aws_region, use_token_cache);
+        Ok(Self {
+            aws_region: aws_region.to_string(),
+            account_id,
+            ecr_client,
+            ecr_auth,
+        })
+    }
+
+    fn image(&self, repo_name: &str, reference: &str) -> String {
+        let separator = if reference.starts_with("sha256:") {
+            '@'
+        } else {
+            ':'
+        };
+        format!(
+            "{}.dkr.ecr.{}.amazonaws.com/{}{}{}",
+            self.account_id, self.aws_region, repo_name, separator, reference
+        )
+    }
+}
+
+/// Settings of a build started from an existing image
+struct RebuildSettings {
+    alias: String,
+    memory_mb: u32,
+    cpu_count: u32,
+    start_cmd: Option<String>,
+    ready_cmd: Option<String>,
+}
+
+impl RebuildSettings {
+    /// Resources of `current` with the start and ready commands of the alias's `aws_e2b.toml` template
+    fn new(alias: &str, current: &TemplateInfo, e2b_cfg: &E2bConfigToml) -> Result<Self> {
+        let settings = settings_for_alias(e2b_cfg, alias)?;
+        Ok(Self {
+            alias: alias.to_string(),
+            memory_mb: current
+                .memory_mb
+                .or(settings.memory_mb)
+                .unwrap_or(DEFAULT_MEMORY_MB),
+            cpu_count: current
+                .cpu_count
+                .or(settings.cpu_count)
+                .unwrap_or(DEFAULT_CPU_COUNT),
+            start_cmd: settings.start_cmd,
+            ready_cmd: settings.ready_cmd,
+        })
+    }
+}
+
+/// Build `template_id` from the image `digest` of `source_repo` and wait for the build
+///
+/// The alias is passed with the build, which is the only way the e2b API assigns
+/// aliases. The image is retagged with the new build ID in the template's
+/// repository: through ECR within one repository, with docker across repositories.
+async fn rebuild_from_image(
+    cluster: &Cluster,
+    registry: &Registry,
+    template_id: &str,
+    source_repo: &str,
+    digest: &str,
+    settings: &RebuildSettings,
+) -> Result<String> {
+    let source_image = registry.image(source_repo, digest);
+    let (build_id, template_id) = build_template(
+        &cluster.e2b_domain,
+        &cluster.access_token,
+        &format!("FROM {}", source_image),
+        settings.memory_mb,
+        settings.cpu_count,
+        settings.start_cmd.clone(),
+        settings.ready_cmd.clone(),
+        Some(settings.alias.clone()),
+        Some(template_id.to_string()),
+    )
+    .await?;
+    info!("buildID: {}", build_id);
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    if repo_name == source_repo {
+        add_image_tag(&registry.ecr_client, &repo_name, digest, &build_id).await?;
+    } else {
+        create_ecr_repo_if_needed(&registry.ecr_client, &template_id).await?;
+        let auth = registry.ecr_auth.get().await?;
+        let target = registry.image(&repo_name, &build_id);
+        pull_docker_image(&source_image, Some(&auth.credentials)).await?;
+        tag_image(&source_image, &target).await?;
+        push_image(&target, &auth.credentials).await?;
+    }
+    ClusterBuild {
+        aws_region: registry.aws_region.clone(),
+        e2b_domain: cluster.e2b_domain.clone(),
+        access_token: cluster.access_token.clone(),
+        template_id,
+        build_id: build_id.clone(),
+    }
+    .finish()
+    .await?;
+    Ok(build_id)
+}
+
+/// Dispatch `template alias` subcommands
+pub async fn run_alias_command(command: AliasCommand) -> Result<()> {
+    let user_cfg = read_user_config()?;
+    let cluster = Cluster::primary().await?;
+    match command {
+        AliasCommand::Set(args) => run_alias_set(&cluster, user_cfg.as_ref(), args).await,
+        AliasCommand::List => {
+            let templates = list_templates(&cluster.e2b_domain, &cluster.access_token).await?;
+            let rows: Vec<[&str; 3]> = templates
+                .iter()
+                .flat_map(|t| {
+                    t.aliases.iter().flatten().map(|alias| {
+                        [
+                            alias.as_str(),
+                            t.template_id.as_str(),
+                            Some(t.build_id.as_str())
+                                .filter(|b| !b.is_empty())
+                                .unwrap_or("-"),
+                        ]
+                    })
+                })
+                .collect();
+            println!(
+                "{}",
+                format_table(["ALIAS", "TEMPLATE ID", "BUILD ID"], rows)
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Make the alias serve the current build of a template
+///
+/// e2b rejects a build that claims an alias another template has, so when the
+/// alias is taken, the template that has it is rebuilt from the target's image.
+/// Otherwise the target itself is rebuilt from its own image with the alias.
+async fn run_alias_set(
+    cluster: &Cluster,
+    user_cfg: Option<&UserConfig>,
+    args: AliasSetArgs,
+) -> Result<()> {
+    let templates = list_templates(&cluster.e2b_domain, &cluster.access_token).await?;
+    let target = templates
+        .iter()
+        .find(|t| t.template_id == args.template_id)
+        .ok_or_else(|| anyhow!("Template {} was not found", args.template_id))?;
+    if target.build_id.is_empty() {
+        return Err(anyhow!(
+            "Template {} has no ready build to point the alias at",
+            target.template_id
+        ));
+    }
+    let holder = alias_holder(&templates, &args.alias);
+    if holder.is_some_and(|h| h.template_id == target.template_id) {
+        info!(
+            "Alias {} already points at template {}",
+            args.alias, target.template_id
+        );
+        return Ok(());
+    }
+    let rebuilt = holder.unwrap_or(target);
+
+    let (e2b_cfg, _) = load_e2b_toml(args.config_path.as_deref(), args.no_config)?;
+    let registry = Registry::connect(&resolve_aws_region(user_cfg)?, user_cfg).await?;
+    let source_repo = format!("{}/{}", ECR_REPO_PREFIX, target.template_id);
+    let digest = ecr_image_digest(&registry.ecr_client, &source_repo, &target.build_id)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "Image {}:{} was not found in ECR",
+                source_repo,
+                target.build_id
+            )
+        })?;
+    info!(
+        "Building template {} from the image of template {} build {} ({})",
+        rebuilt.template_id, target.template_id, target.build_id, digest
+    );
+    let settings = RebuildSettings::new(&args.alias, target, &e2b_cfg)?;
+    let build_id = rebuild_from_image(
+        cluster,
+        &registry,
+        &rebuilt.template_id,
+        &source_repo,
+        &digest,
+        &settings,
+    )
+    .await?;
+    info!(
+        "Alias {} (template {}, build {}) now serves the image of template {} build {}",
+        args.alias, rebuilt.template_id, build_id, target.template_id, target.build_id
+    );
+    Ok(())
+}
+
+fn alias_holder<'a>(templates: &'a [TemplateInfo], alias: &str) -> Option<&'a TemplateInfo> {
+    templates
+        .iter()
+        .find(|t| t.aliases.iter().flatten().any(|a| a == alias))
+}
+
+/// A pushed image in a template repository; its tags include the build IDs that used it
+#[derive(Debug)]
+struct BuildImage {
+    digest: String,
+    pushed_at: i64,
+    tags: Vec<String>,
+}
+
+/// Core logic for the `template rollback` subcommand
+///
+/// A new build of the template is started with an earlier build's image, which
+/// is retagged in ECR under the new build ID instead of being built again. Each
+/// `[[replicas]]` cluster then rebuilds its template with the alias from the
+/// image with the same digest in its own region.
+pub async fn run_template_rollback(args: RollbackArgs) -> Result<()> {
+    let (e2b_cfg, _) = load_e2b_toml(args.config_path.as_deref(), args.no_config)?;
+    let user_cfg = read_user_config()?;
+    let cluster = Cluster::primary().await?;
+    let current = lookup_current_build(
+        &cluster.e2b_domain,
+        &cluster.access_token,
+        None,
+        Some(&args.alias),
+    )
+    .await?
+    .ok_or_else(|| {
+        anyhow!(
+            "No template with the alias {} has a ready build",
+            args.alias
+        )
+    })?;
+
+    let registry =
+        Registry::connect(&resolve_aws_region(user_cfg.as_ref())?, user_cfg.as_ref()).await?;
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, current.template_id);
+    let images = list_build_images(&registry.ecr_client, &repo_name).await?;
+    let image = select_rollback_image(&images, &current.build_id, args.to_build.as_deref())?;
+    info!(
+        "Rolling back template {} from build {} to the image of {} ({})",
+        current.template_id,
+        current.build_id,
+        image.tags.join(", "),
+        image.digest
+    );
+    let settings = RebuildSettings::new(&args.alias, &current, &e2b_cfg)?;
+    rebuild_from_image(
+        &cluster,
+        &registry,
+        &current.template_id,
+        &repo_name,
+        &image.digest,
+        &settings,
+    )
+    .await?;
+    info!("Rollback of {} completed", args.alias);
+
+    let replicas = user_cfg
+        .as_ref()
+        .and_then(|c| c.replicas.clone())
+        .unwrap_or_default();
+    let mut failed = 0;
+    for replica in &replicas {
+        match rollback_replica(
+            replica,
+            &cluster,
+            user_cfg.as_ref(),
+            &image.digest,
+            &settings,
+        )
+        .await
+        {
+            Ok(build_id) => info!(
+                "  {} ({}): rolled back, buildID {}",
+                replica.aws_region, replica.e2b_domain, build_id
+            ),
+            Err(err) => {
+                failed += 1;
+                error!(
+                    "  {} ({}): failed: {:#}",
+                    replica.aws_region, replica.e2b_domain, err
+                );
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "rollback failed in {} of {} replica regions",
+            failed,
+            replicas.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Roll back the replica template with the same alias to the image with `digest`
+///
+/// Images copied or replicated from the primary registry keep their digest, so
+/// the image chosen there is found by it.
+async fn rollback_replica(
+    replica: &ReplicaSection,
+    primary: &Cluster,
+    user_cfg: Option<&UserConfig>,
+    digest: &str,
+    settings: &RebuildSettings,
+) -> Result<String> {
+    let cluster = Cluster::replica(replica, primary).await?;
+    let current = lookup_current_build(
+        &cluster.e2b_domain,
+        &cluster.access_token,
+        None,
+        Some(&settings.alias),
+    )
+    .await?
+    .ok_or_else(|| {
+        anyhow!(
+            "no template with the alias {} has a ready build",
+            settings.alias
+        )
+    })?;
+    let registry = Registry::connect(&replica.aws_region, user_cfg).await?;
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, current.template_id);
+    let images = list_build_images(&registry.ecr_client, &repo_name)
+        .await
+        .with_context(|| format!("failed to list images in {}", replica.aws_region))?;
+    if !images.iter().any(|i| i.digest == digest) {
+        return Err(anyhow!("image {} is not in {}", digest, repo_name));
+    }
+    rebuild_from_image(
+        &cluster,
+        &registry,
+        &current.template_id,
+        &repo_name,
+        digest,
+        settings,
+    )
+    .await
+}
+
+/// Images of the template repository, newest first
+async fn list_build_images(ecr_client: &ecr::Client, repo_name: &str) -> Result<Vec<BuildImage>> {
+    let details = ecr_client
+        .describe_images()
+        .repository_name(repo_name)
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .await
+        .map_err(|err| ecr_error(&format!("describe images in {}", repo_name), err))?;
+    let mut images: Vec<BuildImage> = details
+        .into_iter()
+        .filter_map(|d| {
+            Some(BuildImage {
+                digest: d.image_digest()?.to_string(),
+                pushed_at: d.image_pushed_at().map(|t| t.secs()).unwrap_or_default(),
+                tags: d.image_tags().to_vec(),
+            })
+        })
+        .collect();
+    images.sort_by_key(|i| std::cmp::Reverse(i.pushed_at));
+    Ok(images)
+}
+
+/// The image of `to_build`, or else the newest image pushed before the current build's
+fn select_rollback_image<'a>(
+    images: &'a [BuildImage],
+    current_build: &str,
+    to_build: Option<&str>,
+) -> Result<&'a BuildImage> {
+    let tagged = |build: &str| images.iter().find(|i| i.tags.iter().any(|t| t == build));
+    if let Some(build) = to_build {
+        return tagged(build)
+            .ok_or_else(|| anyhow!("No image tagged with build {} was found", build));
+    }
+    let current = tagged(current_build)
+        .ok_or_else(|| anyhow!("No image tagged with the current build {}", current_build))?;
+    images
+        .iter()
+        .find(|i| i.pushed_at < current.pushed_at && i.digest != current.digest)
+        .ok_or_else(|| anyhow!("No image older than the current build {}", current_build))
+}
+
+/// `[e2b]` settings of the `aws_e2b.toml` template that uses `alias`
+///
+/// The API does not report start and ready commands, so they are taken from here.
+fn settings_for_alias(e2b_cfg: &E2bConfigToml, alias: &str) -> Result<E2bSection> {
+    for name in e2b_cfg.template_names() {
+        let e2b = e2b_cfg.resolve_template(&name)?.e2b.unwrap_or_default();
+        if e2b.alias.as_deref() == Some(alias) {
+            return Ok(e2b);
+        }
+    }
+    Ok(e2b_cfg.e2b.clone().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(digest: &str, pushed_at: i64, tags: &[&str]) -> BuildImage {
+        BuildImage {
+            digest: digest.to_string(),
+            pushed_at,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn select_rollback_image_picks_the_previous_push() {
+        let images = vec![
+            image("sha256:c", 30, &["b3", "fp-123"]),
+            image("sha256:b", 20, &["b2"]),
+            image("sha256:a", 10, &["b1"]),
+        ];
+        assert_eq!(
+            select_rollback_image(&images, "b3", None).unwrap().digest,
+            "sha256:b"
+        );
+        assert_eq!(
+            select_rollback_image(&images, "b3", Some("b1"))
+                .unwrap()
+                .digest,
+            "sha256:a"
+        );
+        assert!(select_rollback_image(&images, "b1", None).is_err());
+        assert!(select_rollback_image(&images, "b3", Some("b9")).is_err());
+    }
+}
@@ -1,13 +1,54 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 /// All arguments for the `template build` subcommand
 #[derive(Parser, Debug)]
 pub struct BuildArgs {
-    /// Optional path to the configuration file, defaulting to `aws_e2b.toml` in the current directory
+    /// Name of a `[templates.<name>]` table in `aws_e2b.toml` to build
+    #[arg(value_name = "TEMPLATE", conflicts_with = "all")]
+    pub template: Option<String>,
+
+    /// Build every template defined in `aws_e2b.toml`
+    #[arg(long = "all")]
+    pub all: bool,
+
+    /// Rebuild even when the current build already matches the inputs
+    #[arg(long = "force")]
+    pub force: bool,
+
+    /// Format of the build results printed to stdout
+    #[arg(long = "output", value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Also write the build results as JSON to this file
+    #[arg(long = "result-file")]
+    pub result_file: Option<PathBuf>,
+
+    /// Rebuild whenever the Dockerfile, `aws_e2b.toml` or build context changes
+    #[arg(long = "watch")]
+    pub watch: bool,
+
+    /// Do not record the git commit, branch and remote as image labels, ECR tags and results
+    #[arg(long = "no-git-metadata")]
+    pub no_git_metadata: bool,
+
+    /// CI integration: step outputs, job summary, log groups and error annotations
+    #[arg(long = "ci", value_enum, default_value_t = CiMode::Auto)]
+    pub ci: CiMode,
+
+    /// Number of templates to build at the same time
+    #[arg(long = "jobs", short = 'j', default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+
+    /// Optional path to the configuration file, defaulting to the nearest `aws_e2b.toml`
+    /// in the current directory or its parents (up to the git root)
     #[arg(long = "config")]
     pub config_path: Option<PathBuf>,
 
+    /// Ignore any `aws_e2b.toml` and use command-line values and defaults only
+    #[arg(long = "no-config", conflicts_with = "config_path")]
+    pub no_config: bool,
+
     #[command(flatten)]
     pub e2b: E2bArgs,
 
@@ -57,6 +98,14 @@ pub struct DockerArgs {
     /// Base image to use when neither Dockerfile nor ECR image is provided
     #[arg(long = "base-image", help_heading = "DOCKER")]
     pub base_image: Option<String>,
+
+    /// Pull the base image through an ECR pull-through cache instead of the public registry
+    #[arg(long = "pull-through-cache", help_heading = "DOCKER")]
+    pub pull_through_cache: bool,
+
+    /// Extra ECR tag for the pushed image, added to the tags in `aws_e2b.toml`; repeatable
+    #[arg(long = "tag", value_name = "TAG", help_heading = "DOCKER")]
+    pub tags: Vec<String>,
 }
 
 /// Arguments for the `template list` subcommand
@@ -74,11 +123,56 @@ pub struct ListArgs {
     about = "AWS wrapper for e2b templates and sandboxes"
 )]
 pub struct AwsE2bCli {
+    /// Profile from `[profiles.<name>]` in ~/.aws_e2b/config.toml (or set AWS_E2B_PROFILE)
+    #[arg(long = "profile", global = true)]
+    pub profile: Option<String>,
+
+    /// User configuration file to use instead of ~/.aws_e2b/config.toml (or set AWS_E2B_CONFIG)
+    #[arg(long = "user-config", global = true)]
+    pub user_config: Option<PathBuf>,
+
+    /// Refuse to read ~/.aws_e2b/config.toml when other users can access it
+    #[arg(long = "strict", global = true)]
+    pub strict: bool,
+
     /// Supported subcommands for aws_e2b
     #[command(subcommand)]
     pub command: AwsE2bCommand,
 }
 
+impl AwsE2bCli {
+    /// Take global options written after `sandbox` out of the forwarded arguments
+    ///
+    /// `sandbox` forwards everything after it, so `--profile`, `--user-config` and
+    /// `--strict` would otherwise reach the e2b CLI. Arguments after `--` are kept.
+    pub fn hoist_sandbox_globals(&mut self) {
+        let AwsE2bCommand::Sandbox(sandbox) = &mut self.command else {
+            return;
+        };
+        let mut forwarded = Vec::new();
+        let mut args = std::mem::take(&mut sandbox.args).into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            match flag {
+                "--" => {
+                    forwarded.push(arg);
+                    forwarded.extend(args.by_ref());
+                }
+                "--profile" => self.profile = inline.or_else(|| args.next()),
+                "--user-config" => {
+                    self.user_config = inline.or_else(|| args.next()).map(PathBuf::from)
+                }
+                "--strict" if inline.is_none() => self.strict = true,
+                _ => forwarded.push(arg),
+            }
+        }
+        sandbox.args = forwarded;
+    }
+}
+
 /// Subcommands available in aws_e2b
 #[derive(Subcommand, Debug)]
 pub enum AwsE2bCommand {
@@ -90,6 +184,18 @@ pub enum AwsE2bCommand {
     },
     /// Forward sandbox subcommands to the official e2b CLI
     Sandbox(SandboxArgs),
+    /// Manage the user configuration
+    Config {
+        /// Operations related to the user configuration
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Verify an e2b access token and store it in the user configuration
+    Login(LoginArgs),
+    /// Remove stored e2b credentials from the active profile of the user configuration
+    Logout(LogoutArgs),
+    /// Act as a Docker credential helper for Amazon ECR registries
+    CredentialHelper(CredentialHelperArgs),
 }
 
 /// Template-related subcommands
@@ -99,6 +205,225 @@ pub enum TemplateCommand {
     Build(BuildArgs),
     /// List templates for a team
     List(ListArgs),
+    /// Scaffold an `aws_e2b.toml` and a starter Dockerfile
+    Init(TemplateInitArgs),
+    /// Copy the current build of a template to another profile's cluster without rebuilding
+    Promote(PromoteArgs),
+    /// Manage template aliases
+    Alias {
+        /// Operations related to aliases
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+    /// Point the template with an alias back at an earlier build's image
+    Rollback(RollbackArgs),
+}
+
+/// Alias subcommands
+#[derive(Subcommand, Debug)]
+pub enum AliasCommand {
+    /// Make an alias serve the current build of a template
+    Set(AliasSetArgs),
+    /// List aliases and the templates they point at
+    List,
+}
+
+/// Arguments for the `template alias set` subcommand
+#[derive(Parser, Debug)]
+pub struct AliasSetArgs {
+    /// Alias to set
+    pub alias: String,
+
+    /// Template identifier whose current build the alias should serve
+    pub template_id: String,
+
+    /// Optional path to the configuration file, defaulting to the nearest `aws_e2b.toml`
+    /// in the current directory or its parents (up to the git root)
+    #[arg(long = "config")]
+    pub config_path: Option<PathBuf>,
+
+    /// Ignore any `aws_e2b.toml`; start and ready commands are then left unset
+    #[arg(long = "no-config", conflicts_with = "config_path")]
+    pub no_config: bool,
+}
+
+/// Arguments for the `template rollback` subcommand
+#[derive(Parser, Debug)]
+pub struct RollbackArgs {
+    /// Alias of the template to roll back
+    pub alias: String,
+
+    /// Build whose image to use, defaulting to the one pushed before the current build
+    #[arg(long = "to-build")]
+    pub to_build: Option<String>,
+
+    /// Optional path to the configuration file, defaulting to the nearest `aws_e2b.toml`
+    /// in the current directory or its parents (up to the git root)
+    #[arg(long = "config")]
+    pub config_path: Option<PathBuf>,
+
+    /// Ignore any `aws_e2b.toml`; start and ready commands are then left unset
+    #[arg(long = "no-config", conflicts_with = "config_path")]
+    pub no_config: bool,
+}
+
+/// User configuration subcommands
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Set the default profile used when `--profile` is not given
+    UseContext(UseContextArgs),
+    /// Print effective build settings and where each value comes from
+    Show(BuildArgs),
+    /// Write ~/.aws_e2b/config.toml, prompting for missing values
+    Init(ConfigInitArgs),
+    /// Check aws_e2b.toml and the user configuration for errors
+    Validate(ValidateArgs),
+    /// Restrict ~/.aws_e2b/config.toml to the current user
+    FixPermissions,
+}
+
+/// Arguments for the `config use-context` subcommand
+#[derive(Parser, Debug)]
+pub struct UseContextArgs {
+    /// Name of a `[profiles.<name>]` table in ~/.aws_e2b/config.toml
+    pub name: String,
+}
+
+/// Arguments for the `config validate` subcommand
+#[derive(Parser, Debug)]
+pub struct ValidateArgs {
+    /// Optional path to the configuration file, defaulting to the nearest `aws_e2b.toml`
+    /// in the current directory or its parents (up to the git root)
+    #[arg(long = "config")]
+    pub config_path: Option<PathBuf>,
+}
+
+/// Arguments for the `login` subcommand
+#[derive(Parser, Debug)]
+pub struct LoginArgs {
+    /// Access token to store; prompted for without echo when omitted
+    ///
+    /// Prefer `--token-stdin`: a token on the command line is visible to other processes.
+    #[arg(long = "token", conflicts_with_all = ["browser", "token_stdin"])]
+    pub token: Option<String>,
+
+    /// Read the access token from stdin, e.g. `echo "$TOKEN" | aws_e2b login --token-stdin`
+    #[arg(long = "token-stdin", conflicts_with = "browser")]
+    pub token_stdin: bool,
+
+    /// Log in through the browser with the official e2b CLI
+    #[arg(long = "browser")]
+    pub browser: bool,
+
+    /// Team identifier or name to select; prompted for when several teams exist
+    #[arg(long = "team")]
+    pub team: Option<String>,
+
+    /// Domain of the e2b cluster, stored in the configuration when given
+    #[arg(long = "e2b-domain")]
+    pub e2b_domain: Option<String>,
+}
+
+/// Arguments for the `logout` subcommand
+#[derive(Parser, Debug)]
+pub struct LogoutArgs {
+    /// Also remove the stored team identifier
+    #[arg(long = "team")]
+    pub team: bool,
+}
+
+/// Arguments for the `config init` subcommand
+#[derive(Parser, Debug)]
+pub struct ConfigInitArgs {
+    /// AWS region hosting the ECR registry
+    #[arg(long = "aws-region")]
+    pub aws_region: Option<String>,
+
+    /// Domain of the e2b cluster
+    #[arg(long = "e2b-domain")]
+    pub e2b_domain: Option<String>,
+
+    /// e2b access token
+    #[arg(long = "e2b-access-token")]
+    pub e2b_access_token: Option<String>,
+
+    /// e2b team identifier
+    #[arg(long = "e2b-team-id")]
+    pub e2b_team_id: Option<String>,
+
+    /// e2b API key
+    #[arg(long = "e2b-api-key")]
+    pub e2b_api_key: Option<String>,
+
+    /// Do not prompt; use flags and existing values only
+    #[arg(long = "non-interactive")]
+    pub non_interactive: bool,
+
+    /// Skip checking AWS credentials and the access token
+    #[arg(long = "no-verify")]
+    pub no_verify: bool,
+}
+
+/// Arguments for the `template init` subcommand
+#[derive(Parser, Debug)]
+pub struct TemplateInitArgs {
+    /// Directory to create the files in, defaulting to the current directory
+    #[arg(long = "dir")]
+    pub dir: Option<PathBuf>,
+
+    /// Alias for the template
+    #[arg(long = "alias")]
+    pub alias: Option<String>,
+
+    /// Memory size in megabytes
+    #[arg(long = "memory-mb")]
+    pub memory_mb: Option<u32>,
+
+    /// Number of CPU cores
+    #[arg(long = "cpu-count")]
+    pub cpu_count: Option<u32>,
+
+    /// Command to execute after the template starts
+    #[arg(long = "start-cmd")]
+    pub start_cmd: Option<String>,
+
+    /// Command to check whether the template is ready
+    #[arg(long = "ready-cmd")]
+    pub ready_cmd: Option<String>,
+
+    /// Base image for the starter Dockerfile
+    #[arg(long = "base-image")]
+    pub base_image: Option<String>,
+
+    /// Overwrite existing files
+    #[arg(long = "force")]
+    pub force: bool,
+}
+
+/// Arguments for the `template promote` subcommand
+#[derive(Parser, Debug)]
+pub struct PromoteArgs {
+    /// Source as `<profile>:<template>`; the template is a `[templates.<name>]` table
+    /// in `aws_e2b.toml`, or a template ID or alias on the source cluster
+    #[arg(long = "from", value_name = "PROFILE:TEMPLATE")]
+    pub from: String,
+
+    /// Profile of the cluster that receives the build
+    #[arg(long = "to", value_name = "PROFILE")]
+    pub to: String,
+
+    /// Existing template on the target cluster to update instead of looking it up by alias
+    #[arg(long = "template-id")]
+    pub template_id: Option<String>,
+
+    /// Optional path to the configuration file, defaulting to the nearest `aws_e2b.toml`
+    /// in the current directory or its parents (up to the git root)
+    #[arg(long = "config")]
+    pub config_path: Option<PathBuf>,
+
+    /// Ignore any `aws_e2b.toml`; start and ready commands are then left unset
+    #[arg(long = "no-config", conflicts_with = "config_path")]
+    pub no_config: bool,
 }
 
 /// Capture arguments after the `sandbox` subcommand for forwarding
@@ -114,3 +439,69 @@ pub struct SandboxArgs {
     #[arg(required = true)]
     pub args: Vec<String>,
 }
+
+/// Arguments for the `credential-helper` subcommand
+#[derive(Parser, Debug)]
+pub struct CredentialHelperArgs {
+    /// Credential helper protocol action invoked by Docker
+    #[arg(value_enum)]
+    pub action: CredentialHelperAction,
+}
+
+/// Actions defined by the docker-credential-helper protocol
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialHelperAction {
+    /// Print credentials for the server URL read from stdin
+    Get,
+    /// Accept credentials from Docker; ECR tokens are not stored
+    Store,
+    /// Remove stored credentials; ECR tokens are not stored
+    Erase,
+    /// List the ECR registry of the configured account and region
+    List,
+}
+
+/// Output format of `template build`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Log lines and, for several templates, a summary table
+    Text,
+    /// A JSON manifest of the build results
+    Json,
+}
+
+/// CI system to integrate with in `template build`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiMode {
+    /// Detect GitHub Actions or GitLab CI from the environment
+    Auto,
+    Github,
+    Gitlab,
+    Off,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_global_options_are_not_forwarded() {
+        let mut cli = AwsE2bCli::parse_from([
+            "aws_e2b",
+            "sandbox",
+            "list",
+            "--profile",
+            "prod",
+            "--strict",
+            "--",
+            "--profile=x",
+        ]);
+        cli.hoist_sandbox_globals();
+        assert_eq!(cli.profile.as_deref(), Some("prod"));
+        assert!(cli.strict);
+        let AwsE2bCommand::Sandbox(sandbox) = cli.command else {
+            panic!("expected the sandbox command");
+        };
+        assert_eq!(sandbox.args, ["list", "--", "--profile=x"]);
+    }
+}
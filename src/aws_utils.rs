@@ -1,9 +1,30 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::{Region, SdkConfig};
 use aws_sdk_ecr as ecr;
+use aws_sdk_ecr::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_ecr::operation::create_repository::CreateRepositoryError;
+use aws_sdk_ecr::operation::describe_repositories::DescribeRepositoriesError;
+use aws_sdk_ecr::operation::put_image::PutImageError;
+use aws_sdk_ecr::types::ImageIdentifier;
 use aws_sdk_sts as sts;
 use base64::Engine;
 use bollard::auth::DockerCredentials;
 use log::info;
+use serde::{Deserialize, Serialize};
+
+/// Repository prefix under which e2b expects template base images
+pub const ECR_REPO_PREFIX: &str = "e2bdev/base";
+
+/// Load the shared AWS SDK configuration for the given region
+pub async fn load_aws_config(aws_region: &str) -> SdkConfig {
+    let region = Region::new(aws_region.to_string());
+    let region_provider = RegionProviderChain::first_try(region);
+    aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(region_provider)
+        .load()
+        .await
+}
 
 /// Retrieve the AWS account identifier of the current caller
 pub async fn fetch_aws_account_id(sts_client: &sts::Client) -> Result<String> {
@@ -11,8 +32,17 @@ pub async fn fetch_aws_account_id(sts_client: &sts::Client) -> Result<String> {
     Ok(resp.account.as_deref().unwrap_or("").to_string())
 }
 
+/// ECR registry endpoint and Docker credentials along with their expiry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EcrAuth {
+    pub registry: String,
+    pub credentials: DockerCredentials,
+    /// Unix timestamp (seconds) after which the token is no longer valid
+    pub expires_at: i64,
+}
+
 /// Retrieve authentication information from Amazon ECR
-pub async fn get_ecr_auth(ecr_client: &ecr::Client) -> Result<(String, DockerCredentials)> {
+pub async fn get_ecr_auth(ecr_client: &ecr::Client) -> Result<EcrAuth> {
     let auth = ecr_client.get_authorization_token().send().await?;
     let data = auth
         .authorization_data
@@ -32,31 +62,206 @@ pub async fn get_ecr_auth(ecr_client: &ecr::Client) -> Result<(String, DockerCre
     let username = parts.next().unwrap_or("").to_string();
     let password = parts.next().unwrap_or("").to_string();
     let server = data.proxy_endpoint.unwrap_or_default();
+    // ECR tokens are valid for 12 hours; assume that if the expiry is not reported
+    let expires_at = data
+        .expires_at
+        .map(|t| t.secs())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() + 12 * 60 * 60);
     let creds = DockerCredentials {
         username: Some(username),
         password: Some(password),
         serveraddress: Some(server.clone()),
         ..Default::default()
     };
-    Ok((server, creds))
+    Ok(EcrAuth {
+        registry: server,
+        credentials: creds,
+        expires_at,
+    })
 }
 
 /// Create the repository if it does not already exist
+///
+/// Only a `RepositoryNotFoundException` triggers creation; any other failure is
+/// reported as-is. A concurrent creation by another job is treated as success.
 pub async fn create_ecr_repo_if_needed(ecr_client: &ecr::Client, template_id: &str) -> Result<()> {
-    let repo_name = format!("e2bdev/base/{}", template_id);
-    if ecr_client
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    match ecr_client
         .describe_repositories()
         .repository_names(repo_name.clone())
         .send()
         .await
-        .is_err()
     {
-        info!("Creating ECR repository: {}", repo_name);
-        ecr_client
-            .create_repository()
-            .repository_name(repo_name)
-            .send()
-            .await?;
+        Ok(_) => return Ok(()),
+        Err(err) => {
+            if !matches!(
+                err.as_service_error(),
+                Some(DescribeRepositoriesError::RepositoryNotFoundException(_))
+            ) {
+                return Err(ecr_error(
+                    &format!("describe ECR repository {}", repo_name),
+                    err,
+                ));
+            }
+        }
+    }
+
+    info!("Creating ECR repository: {}", repo_name);
+    match ecr_client
+        .create_repository()
+        .repository_name(repo_name.clone())
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err)
+            if matches!(
+                err.as_service_error(),
+                Some(CreateRepositoryError::RepositoryAlreadyExistsException(_))
+            ) =>
+        {
+            info!("ECR repository was created concurrently: {}", repo_name);
+            Ok(())
+        }
+        Err(err) => Err(ecr_error(
+            &format!("create ECR repository {}", repo_name),
+            err,
+        )),
+    }
+}
+
+/// Add `new_tag` to the image tagged `source_tag` without pushing it again
+///
+/// The manifest is fetched and re-put under the new tag, which ECR treats as a
+/// retag; a tag already pointing at the same manifest is left as is.
+/// `source_tag` may also be a `sha256:` digest.
+pub async fn add_image_tag(
+    ecr_client: &ecr::Client,
+    repo_name: &str,
+    source_tag: &str,
+    new_tag: &str,
+) -> Result<()> {
+    let image_id = if source_tag.starts_with("sha256:") {
+        ImageIdentifier::builder().image_digest(source_tag)
+    } else {
+        ImageIdentifier::builder().image_tag(source_tag)
+    };
+    let resp = ecr_client
+        .batch_get_image()
+        .repository_name(repo_name)
+        .image_ids(image_id.build())
+        .send()
+        .await
+        .map_err(|err| ecr_error(&format!("read image {}:{}", repo_name, source_tag), err))?;
+    let image = resp
+        .images()
+        .first()
+        .ok_or_else(|| anyhow!("image {}:{} was not found", repo_name, source_tag))?;
+    let manifest = image
+        .image_manifest()
+        .ok_or_else(|| anyhow!("image {}:{} has no manifest", repo_name, source_tag))?;
+    match ecr_client
+        .put_image()
+        .repository_name(repo_name)
+        .image_manifest(manifest)
+        .set_image_manifest_media_type(image.image_manifest_media_type().map(str::to_string))
+        .image_tag(new_tag)
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err)
+            if matches!(
+                err.as_service_error(),
+                Some(PutImageError::ImageAlreadyExistsException(_))
+            ) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(ecr_error(
+            &format!("tag image {}:{} as {}", repo_name, source_tag, new_tag),
+            err,
+        )),
+    }
+}
+
+/// Digest of the image tagged `tag` in `repo_name`
+pub async fn ecr_image_digest(
+    ecr_client: &ecr::Client,
+    repo_name: &str,
+    tag: &str,
+) -> Result<Option<String>> {
+    let resp = ecr_client
+        .describe_images()
+        .repository_name(repo_name)
+        .image_ids(ImageIdentifier::builder().image_tag(tag).build())
+        .send()
+        .await
+        .map_err(|err| ecr_error(&format!("describe image {}:{}", repo_name, tag), err))?;
+    Ok(resp
+        .image_details()
+        .first()
+        .and_then(|d| d.image_digest().map(str::to_string)))
+}
+
+/// Convert an ECR SDK error into an error message with an actionable hint
+pub fn ecr_error<E>(action: &str, err: SdkError<E>) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    let hint = match &err {
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) => {
+            Some("AWS could not be reached; check network connectivity and the configured region")
+        }
+        _ => ecr_error_hint(err.code()),
+    };
+    let detail = DisplayErrorContext(&err).to_string();
+    match hint {
+        Some(hint) => anyhow!("failed to {}: {} ({})", action, detail, hint),
+        None => anyhow!("failed to {}: {}", action, detail),
+    }
+}
+
+/// Map well-known AWS error codes to a suggestion for the user
+fn ecr_error_hint(code: Option<&str>) -> Option<&'static str> {
+    match code? {
+        "ExpiredTokenException"
+        | "ExpiredToken"
+        | "UnrecognizedClientException"
+        | "InvalidClientTokenId"
+        | "InvalidSignatureException" => Some(
+            "AWS credentials are missing, invalid or expired; refresh them (e.g. `aws sso login`) and retry",
+        ),
+        "AccessDeniedException" | "AccessDenied" => Some(
+            "the AWS identity lacks the ECR permission for this action; check its IAM policy",
+        ),
+        "ThrottlingException" | "TooManyRequestsException" | "RequestLimitExceeded" => {
+            Some("the request was throttled by AWS; wait a moment and retry")
+        }
+        "LimitExceededException" => {
+            Some("an ECR service quota for this account and region has been reached")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ecr_error_hint_recognizes_auth_errors() {
+        assert!(ecr_error_hint(Some("ExpiredTokenException"))
+            .unwrap()
+            .contains("expired"));
+        assert!(ecr_error_hint(Some("AccessDeniedException"))
+            .unwrap()
+            .contains("permission for this action"));
+    }
+
+    #[test]
+    fn ecr_error_hint_ignores_unknown_codes() {
+        assert!(ecr_error_hint(Some("SomethingElse")).is_none());
+        assert!(ecr_error_hint(None).is_none());
     }
-    Ok(())
 }
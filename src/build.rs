@@ -1,23 +1,48 @@
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::{env, fs};
+use std::{env, fmt, fs};
 
 use anyhow::{anyhow, Context, Result};
-use aws_config::meta::region::RegionProviderChain;
-use aws_config::Region;
 use aws_sdk_ecr as ecr;
 use aws_sdk_sts as sts;
-use log::info;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{error, info, warn};
 
-use crate::args::BuildArgs;
-use crate::aws_utils::{create_ecr_repo_if_needed, fetch_aws_account_id, get_ecr_auth};
-use crate::config::{load_e2b_toml, read_user_config};
-use crate::docker_utils::{build_temp_image, pull_docker_image, push_image, tag_image};
-use crate::e2b_api::{build_template, notify_build_complete, poll_build_status_until_done};
+use crate::args::{BuildArgs, OutputFormat};
+use crate::aws_utils::{
+    add_image_tag, create_ecr_repo_if_needed, ecr_image_digest, fetch_aws_account_id,
+    load_aws_config, ECR_REPO_PREFIX,
+};
+use crate::build_cache::{
+    build_has_fingerprint, dockerfile_base_images, fingerprint, fingerprint_tag, FingerprintInputs,
+    FINGERPRINT_TAG_PREFIX,
+};
+use crate::ci;
+use crate::config::{
+    load_e2b_toml, pick, read_user_config, validate_resources, ClusterSettings, E2bConfigToml,
+    ReplicationMode, ResourceLimits, Source, Sourced,
+};
+use crate::docker_utils::{
+    build_temp_image, pull_docker_image, push_image, remote_image_digest, tag_image,
+};
+use crate::e2b_api::{
+    build_template, list_templates, notify_build_complete, poll_build_status_until_done,
+    TemplateInfo,
+};
+use crate::ecr_auth::EcrAuthProvider;
+use crate::git::{GitMetadata, GIT_TAG_PREFIX};
+use crate::promote::PROMOTED_TAG_PREFIX;
+use crate::pull_through::resolve_pull_through_image;
+use crate::replication::{ensure_replication_rule, wait_for_replication};
+use crate::report::{BuildManifest, BuildParameters, BuildReport, BuildStatus, ReplicaReport};
+use crate::secrets::resolve_secret;
+use crate::watch::{cancel_requested, cancellable, watch_builds};
 
 /// Default configuration
-const DEFAULT_MEMORY_MB: u32 = 4096;
-const DEFAULT_CPU_COUNT: u32 = 4;
-const DEFAULT_IMAGE: &str = "e2bdev/code-interpreter:latest";
+pub const DEFAULT_MEMORY_MB: u32 = 4096;
+pub const DEFAULT_CPU_COUNT: u32 = 4;
+pub const DEFAULT_IMAGE: &str = "e2bdev/code-interpreter:latest";
 
 /// Build method
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -28,7 +53,7 @@ enum BuildType {
 }
 
 /// Add a "Bearer" prefix to the access token if it is missing
-fn format_bearer_token(token: &str) -> String {
+pub fn format_bearer_token(token: &str) -> String {
     let trimmed = token.trim();
     let has_prefix = trimmed
         .get(0..7)
@@ -43,228 +68,1291 @@ fn format_bearer_token(token: &str) -> String {
 
 /// Core logic for the `template build` subcommand
 pub async fn run_template_build(args: BuildArgs) -> Result<()> {
+    ci::init(
+        ci::detect(args.ci, &|name| env::var(name).ok()),
+        args.jobs == 1,
+    );
+    if args.watch {
+        return watch_builds(&args).await;
+    }
+    build_templates(&args).await
+}
+
+/// Build the selected templates once
+pub async fn build_templates(args: &BuildArgs) -> Result<()> {
     // Load optional aws_e2b.toml
-    let (e2b_cfg, e2b_dir) = load_e2b_toml(args.config_path.as_deref())?;
-
-    // Extract template-related parameters from configuration
-    let t_memory_mb = e2b_cfg.e2b.as_ref().and_then(|s| s.memory_mb);
-    let t_cpu_count = e2b_cfg.e2b.as_ref().and_then(|s| s.cpu_count);
-    let t_start_cmd = e2b_cfg.e2b.as_ref().and_then(|s| s.start_cmd.clone());
-    let t_ready_cmd = e2b_cfg.e2b.as_ref().and_then(|s| s.ready_cmd.clone());
-    let t_alias = e2b_cfg.e2b.as_ref().and_then(|s| s.alias.clone());
-    let t_template_id = e2b_cfg.e2b.as_ref().and_then(|s| s.template_id.clone());
-
-    let t_dockerfile = e2b_cfg.docker.as_ref().and_then(|s| s.dockerfile.clone());
-    let t_ecr_image = e2b_cfg.docker.as_ref().and_then(|s| s.ecr_image.clone());
-    let t_docker_image = e2b_cfg.docker.as_ref().and_then(|s| s.docker_image.clone());
-
-    // Parameter priority: command line > aws_e2b.toml > defaults
-    let resolved_memory_mb = args
-        .e2b
-        .memory_mb
-        .or(t_memory_mb)
-        .unwrap_or(DEFAULT_MEMORY_MB);
-    let resolved_cpu = args
-        .e2b
-        .cpu_count
-        .or(t_cpu_count)
-        .unwrap_or(DEFAULT_CPU_COUNT);
-    let resolved_start_cmd = args.e2b.start_cmd.clone().or(t_start_cmd);
-    let resolved_ready_cmd = args.e2b.ready_cmd.clone().or(t_ready_cmd);
-    let resolved_alias = args.e2b.alias.clone().or(t_alias);
-    let resolved_template_id = args.e2b.template_id.clone().or(t_template_id);
-
-    let (build_type, dockerfile_content, base_image_opt, dockerfile_path) = resolve_build_input(
-        &args,
-        t_dockerfile.as_deref(),
-        t_ecr_image.as_deref(),
-        t_docker_image.as_deref(),
-        e2b_dir.as_deref(),
+    let (e2b_cfg, e2b_path) = load_e2b_toml(args.config_path.as_deref(), args.no_config)?;
+    let e2b_path = e2b_path.as_deref();
+
+    if !args.all && args.template.is_none() {
+        e2b_cfg.select_template(None)?;
+        let result = run_build(args, &e2b_cfg, e2b_path, None).await;
+        write_results(args, std::slice::from_ref(&result))?;
+        return result.outcome;
+    }
+
+    let names = match &args.template {
+        Some(name) => vec![name.clone()],
+        None => e2b_cfg.template_names(),
+    };
+    if names.is_empty() {
+        return Err(anyhow!(
+            "--all requires [templates.<name>] tables in aws_e2b.toml"
+        ));
+    }
+    if args.all && (args.e2b.alias.is_some() || args.e2b.template_id.is_some()) {
+        return Err(anyhow!(
+            "--alias and --template-id identify a single template and cannot be used with --all"
+        ));
+    }
+
+    let deps = e2b_cfg.template_dependencies();
+    let e2b_cfg = &e2b_cfg;
+    let results = schedule_builds(names, &deps, args.jobs.into(), |name| async move {
+        info!("Building template {}", name);
+        run_build(args, e2b_cfg, e2b_path, Some(name)).await
+    })
+    .await;
+    write_results(args, &results)?;
+    if args.output == OutputFormat::Text {
+        println!("{}", format_summary(&results));
+    }
+    let failed = results.iter().filter(|r| r.outcome.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} templates failed to build",
+            failed,
+            results.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Build one template and capture its report, including the error if it failed
+async fn run_build(
+    args: &BuildArgs,
+    e2b_cfg: &E2bConfigToml,
+    e2b_path: Option<&Path>,
+    name: Option<String>,
+) -> TemplateResult {
+    let mut report = BuildReport::new(name.clone());
+    let outcome =
+        build_single_template(args, e2b_cfg, e2b_path, name.as_deref(), &mut report).await;
+    if let Err(err) = &outcome {
+        report.fail(err);
+    }
+    TemplateResult {
+        name: name.unwrap_or_default(),
+        report,
+        outcome,
+    }
+}
+
+/// Print the manifest for `--output json`, write it to `--result-file` and publish it to CI
+fn write_results(args: &BuildArgs, results: &[TemplateResult]) -> Result<()> {
+    let manifest = BuildManifest {
+        templates: results.iter().map(|r| &r.report).collect(),
+    };
+    if let Some(path) = &args.result_file {
+        manifest.write(path)?;
+        info!("Wrote build results to {}", path.display());
+    }
+    if args.output == OutputFormat::Json {
+        println!("{}", manifest.to_json()?);
+    }
+    // A build stopped by --watch is superseded by the rebuild, so CI does not
+    // see it as a failure
+    let published: Vec<&BuildReport> = manifest
+        .templates
+        .iter()
+        .copied()
+        .filter(|r| r.status != BuildStatus::Failed || !cancel_requested())
+        .collect();
+    ci::publish(&published, args.result_file.as_deref())
+}
+
+/// Build `names` with at most `jobs` builds in flight, respecting `depends_on`
+///
+/// A template starts once every selected template it depends on is ready and is
+/// skipped when one of them failed. Dependencies outside the selection are
+/// assumed to be built already. Results are returned in the order of `names`.
+async fn schedule_builds<F, Fut>(
+    names: Vec<String>,
+    deps: &BTreeMap<String, Vec<String>>,
+    jobs: usize,
+    build: F,
+) -> Vec<TemplateResult>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = TemplateResult>,
+{
+    let selected: Vec<String> = names.clone();
+    let mut pending = names;
+    let mut running = FuturesUnordered::new();
+    let mut finished: Vec<TemplateResult> = Vec::new();
+    loop {
+        let mut i = 0;
+        while running.len() < jobs.max(1) && i < pending.len() {
+            let parents: Vec<&String> = deps
+                .get(&pending[i])
+                .into_iter()
+                .flatten()
+                .filter(|p| selected.contains(p))
+                .collect();
+            let state = |parent: &String| {
+                finished
+                    .iter()
+                    .find(|r| &r.name == parent)
+                    .map(|r| r.outcome.is_ok())
+            };
+            if let Some(failed) = parents.iter().find(|p| state(p) == Some(false)) {
+                let name = pending.remove(i);
+                error!("Skipping template {}: dependency {} failed", name, failed);
+                let err = anyhow!("dependency {} failed", failed);
+                finished.push(TemplateResult::failed(name, err));
+                // The skip may in turn decide templates that were already passed over
+                i = 0;
+            } else if parents.iter().all(|p| state(p) == Some(true)) {
+                let name = pending.remove(i);
+                running.push(build(name));
+            } else {
+                i += 1;
+            }
+        }
+        let Some(result) = running.next().await else {
+            break;
+        };
+        if let Err(err) = &result.outcome {
+            error!("Template {} failed: {:#}", result.name, err);
+        }
+        finished.push(result);
+    }
+    // Only reachable with a dependency cycle, which configuration validation rejects
+    for name in pending {
+        finished.push(TemplateResult::failed(
+            name,
+            anyhow!("unresolvable depends_on"),
+        ));
+    }
+    finished.sort_by_key(|r| selected.iter().position(|n| n == &r.name));
+    finished
+}
+
+/// Outcome of building one named template
+struct TemplateResult {
+    name: String,
+    report: BuildReport,
+    outcome: Result<()>,
+}
+
+impl TemplateResult {
+    /// Result for a template that failed before its build started
+    fn failed(name: String, err: anyhow::Error) -> Self {
+        let mut report = BuildReport::new(Some(name.clone()));
+        report.fail(&err);
+        Self {
+            name,
+            report,
+            outcome: Err(err),
+        }
+    }
+}
+
+/// Render the end-of-run table with one row per template
+fn format_summary(results: &[TemplateResult]) -> String {
+    let rows: Vec<[&str; 4]> = results
+        .iter()
+        .map(|r| {
+            [
+                r.name.as_str(),
+                r.report.status.label(),
+                r.report.template_id.as_deref().unwrap_or("-"),
+                r.report.build_id.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect();
+    format_table(["TEMPLATE", "STATUS", "TEMPLATE ID", "BUILD ID"], rows)
+}
+
+/// Left-aligned columns separated by two spaces, with a header row
+pub fn format_table<const N: usize>(header: [&str; N], rows: Vec<[&str; N]>) -> String {
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    std::iter::once(header)
+        .chain(rows)
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Build one template from its `aws_e2b.toml` settings
+async fn build_single_template(
+    args: &BuildArgs,
+    e2b_cfg: &E2bConfigToml,
+    e2b_path: Option<&Path>,
+    template: Option<&str>,
+    report: &mut BuildReport,
+) -> Result<()> {
+    let settings = TemplateSettings::resolve(args, e2b_cfg, e2b_path, template)?;
+    let value = |s: Option<Sourced<String>>| s.map(|s| s.value);
+    let resolved_memory_mb = settings.memory_mb.value;
+    let resolved_cpu = settings.cpu_count.value;
+    let resolved_start_cmd = value(settings.start_cmd);
+    let resolved_ready_cmd = value(settings.ready_cmd);
+    let resolved_alias = value(settings.alias);
+    let resolved_template_id = value(settings.template_id);
+
+    let extra_tags = resolve_extra_tags(
+        &settings.tags,
+        &args.docker.tags,
+        &chrono::Utc::now().format("%Y%m%d").to_string(),
     )?;
 
-    // Read user-level configuration ~/.aws_e2b/config.toml
-    let user_cfg = read_user_config().ok().flatten();
+    let (build_type, dockerfile_content, base_image_opt, dockerfile_path) =
+        resolve_build_input(&settings.image)?;
 
-    // AWS region priority: environment variable > user configuration
-    let user_aws_region = user_cfg
-        .as_ref()
-        .and_then(|c| c.aws.as_ref().and_then(|a| a.aws_region.clone()));
-    let aws_region = env::var("AWS_REGION").ok().or(user_aws_region).ok_or_else(|| {
-        anyhow!(
-            "Missing AWS region: set AWS_REGION or configure [aws].aws_region in ~/.aws_e2b/config.toml"
-        )
-    })?;
+    report.alias = resolved_alias.clone();
+    report.parameters = Some(BuildParameters {
+        memory_mb: resolved_memory_mb,
+        cpu_count: resolved_cpu,
+        start_cmd: resolved_start_cmd.clone(),
+        ready_cmd: resolved_ready_cmd.clone(),
+        source: settings.image.value.to_string(),
+    });
 
-    // e2b domain priority: environment variable > user configuration
-    let user_e2b_domain = user_cfg
-        .as_ref()
-        .and_then(|c| c.e2b.as_ref().and_then(|e| e.e2b_domain.clone()));
-    let e2b_domain = env::var("E2B_DOMAIN").ok().or(user_e2b_domain).ok_or_else(|| {
-        anyhow!(
-            "Missing e2b domain: set E2B_DOMAIN or configure [e2b].e2b_domain in ~/.aws_e2b/config.toml"
-        )
-    })?;
+    if !args.no_git_metadata {
+        let git_dir = dockerfile_path
+            .as_deref()
+            .and_then(Path::parent)
+            .or(e2b_path.and_then(Path::parent))
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        report.git = GitMetadata::read(git_dir).await;
+        if let Some(git) = &report.git {
+            info!(
+                "Git commit {}{}",
+                git.commit,
+                if git.dirty { " (dirty)" } else { "" }
+            );
+        }
+    }
 
-    // Access token priority: environment variable > user configuration
-    let user_token = user_cfg
-        .as_ref()
-        .and_then(|c| c.e2b.as_ref().and_then(|e| e.e2b_access_token.clone()));
-    let raw_access_token = env::var("E2B_ACCESS_TOKEN").ok().or(user_token).ok_or_else(|| {
-        anyhow!(
-            "Missing e2b access token: set E2B_ACCESS_TOKEN or configure [e2b].e2b_access_token in ~/.aws_e2b/config.toml"
-        )
-    })?;
-    let e2b_access_token = format_bearer_token(&raw_access_token);
+    // Read user-level configuration ~/.aws_e2b/config.toml
+    let user_cfg = read_user_config()?;
+    validate_resources(
+        Some(resolved_memory_mb),
+        Some(resolved_cpu),
+        &ResourceLimits::from_user_config(user_cfg.as_ref()),
+    )?;
 
-    if let Some(ref tid) = resolved_template_id {
-        info!("Using existing template ID: {}", tid);
+    // Template IDs differ between clusters, so replica templates can only be
+    // found again by alias; without one every build would create a new template
+    let replicas = user_cfg
+        .as_ref()
+        .and_then(|c| c.replicas.clone())
+        .unwrap_or_default();
+    if !replicas.is_empty() && resolved_alias.is_none() {
+        return Err(anyhow!(
+            "Building on [[replicas]] clusters requires a template alias: set `alias` in aws_e2b.toml or pass --alias"
+        ));
     }
 
-    let (build_id, template_id) = build_template(
-        &e2b_domain,
-        &e2b_access_token,
-        &dockerfile_content,
-        resolved_memory_mb,
-        resolved_cpu,
-        resolved_start_cmd.clone(),
-        resolved_ready_cmd.clone(),
-        resolved_alias.clone(),
-        resolved_template_id.clone(),
-    )
-    .await?;
-    info!("buildID: {}", build_id);
-    info!("templateID: {}", template_id);
+    // Environment > active profile > top-level user configuration
+    let cluster = ClusterSettings::read()?;
+    let aws_region = cluster.require_aws_region()?;
+    let e2b_domain = cluster.require_e2b_domain()?;
+    let raw_access_token = cluster.require_e2b_access_token()?;
+    let e2b_access_token =
+        format_bearer_token(&resolve_secret(&raw_access_token, Some(&aws_region)).await?);
+    report.aws_region = Some(aws_region.clone());
+    report.e2b_domain = Some(e2b_domain.clone());
 
     // Initialize AWS SDK
-    let region = Region::new(aws_region.clone());
-    let region_provider = RegionProviderChain::first_try(region);
-    let shared_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
+    let shared_config = load_aws_config(&aws_region).await;
     let sts_client = sts::Client::new(&shared_config);
     let ecr_client = ecr::Client::new(&shared_config);
 
     let aws_account_id = fetch_aws_account_id(&sts_client).await?;
     info!("AWS Account ID: {}", aws_account_id);
+    report.aws_account_id = Some(aws_account_id.clone());
 
-    let (registry, docker_creds) = get_ecr_auth(&ecr_client).await?;
+    let use_token_cache = user_cfg
+        .as_ref()
+        .and_then(|c| c.aws.as_ref().and_then(|a| a.ecr_token_cache))
+        .unwrap_or(false);
+    let ecr_auth = EcrAuthProvider::new(
+        ecr_client.clone(),
+        &aws_account_id,
+        &aws_region,
+        use_token_cache,
+    );
 
-    create_ecr_repo_if_needed(&ecr_client, &template_id).await?;
+    // Skip the build, before any image is built or pulled, when the template's
+    // current build has the same inputs
+    let stage = report.begin("fingerprint");
+    let registry = format!("{}.dkr.ecr.{}.amazonaws.com", aws_account_id, aws_region);
+    // Rewrite the default base image first, so neither its digest lookup nor the
+    // pull reach the public registry when the pull-through cache is enabled
+    let base_image_opt = match build_type {
+        BuildType::Default => {
+            let chosen = base_image_opt.expect("base image must be provided");
+            let pull_through_settings = user_cfg
+                .as_ref()
+                .and_then(|c| c.aws.as_ref().and_then(|a| a.pull_through_cache.clone()))
+                .unwrap_or_default();
+            if args.docker.pull_through_cache || pull_through_settings.enabled.unwrap_or(false) {
+                Some(
+                    resolve_pull_through_image(
+                        &ecr_client,
+                        &chosen,
+                        &aws_account_id,
+                        &aws_region,
+                        &pull_through_settings,
+                    )
+                    .await?,
+                )
+            } else {
+                Some(chosen)
+            }
+        }
+        _ => base_image_opt,
+    };
+    let fingerprint = build_fingerprint(
+        &build_type,
+        &dockerfile_content,
+        dockerfile_path.as_deref(),
+        base_image_opt.as_deref(),
+        &ecr_client,
+        &registry,
+        resolved_memory_mb,
+        resolved_cpu,
+        resolved_start_cmd.as_deref(),
+        resolved_ready_cmd.as_deref(),
+    )
+    .await?;
+    report.fingerprint = Some(fingerprint.clone());
+    if args.force {
+        info!("Fingerprint {}; rebuilding because of --force", fingerprint);
+    } else if let Some(current) = find_current_build(
+        &e2b_domain,
+        &e2b_access_token,
+        resolved_template_id.as_deref(),
+        resolved_alias.as_deref(),
+    )
+    .await
+    {
+        if build_has_fingerprint(
+            &ecr_client,
+            &current.template_id,
+            &current.build_id,
+            &fingerprint,
+        )
+        .await?
+        {
+            info!(
+                "Template {} is up to date (build {}); use --force to rebuild",
+                current.template_id, current.build_id
+            );
+            // The unchanged image is also the image of this commit
+            if let Some(git) = &report.git {
+                let repo_name = format!("{}/{}", ECR_REPO_PREFIX, current.template_id);
+                if let Err(err) =
+                    add_image_tag(&ecr_client, &repo_name, &current.build_id, &git.tag()).await
+                {
+                    warn!("Could not tag the image with its git commit: {:#}", err);
+                }
+            }
+            report.tags = apply_extra_tags(
+                &ecr_client,
+                &current.template_id,
+                &current.build_id,
+                &extra_tags,
+            )
+            .await;
+            report.finish(stage);
+            report.template_id = Some(current.template_id);
+            report.build_id = Some(current.build_id);
+            report.status = BuildStatus::UpToDate;
+            return Ok(());
+        }
+    }
+    report.finish(stage);
 
     // Prepare the base image
+    let stage = report.begin("prepare_image");
     let base_image = match build_type {
         BuildType::Dockerfile => {
             info!("Base image source: local build from Dockerfile");
-            let path = dockerfile_path.ok_or_else(|| anyhow!("missing Dockerfile path"))?;
-            build_temp_image(&path).await?
+            let path = dockerfile_path
+                .as_deref()
+                .ok_or_else(|| anyhow!("missing Dockerfile path"))?;
+            let labels = report
+                .git
+                .as_ref()
+                .map(GitMetadata::labels)
+                .unwrap_or_default();
+            cancellable(build_temp_image(path, &labels)).await?
         }
         BuildType::EcrImage => {
             let img = base_image_opt.expect("ECR image must be provided");
             info!("Base image source: ECR image {}", img);
-            pull_docker_image(&img, Some(&docker_creds)).await?;
+            let auth = ecr_auth.get().await?;
+            cancellable(pull_docker_image(&img, Some(&auth.credentials))).await?;
             img
         }
         BuildType::Default => {
-            let chosen = args
-                .docker
-                .base_image
-                .clone()
-                .or(t_docker_image)
-                .unwrap_or_else(|| DEFAULT_IMAGE.to_string());
+            let chosen = base_image_opt.expect("base image must be provided");
             info!("Base image: {}", chosen);
-            pull_docker_image(&chosen, None).await?;
+            if chosen.starts_with(&registry) {
+                let auth = ecr_auth.get().await?;
+                cancellable(pull_docker_image(&chosen, Some(&auth.credentials))).await?;
+            } else {
+                cancellable(pull_docker_image(&chosen, None)).await?;
+            }
             chosen
         }
     };
 
+    report.finish(stage);
+
+    if let Some(ref tid) = resolved_template_id {
+        info!("Using existing template ID: {}", tid);
+    }
+
+    let stage = report.begin("create_build");
+    let (build_id, template_id) = build_template(
+        &e2b_domain,
+        &e2b_access_token,
+        &dockerfile_content,
+        resolved_memory_mb,
+        resolved_cpu,
+        resolved_start_cmd.clone(),
+        resolved_ready_cmd.clone(),
+        resolved_alias.clone(),
+        resolved_template_id.clone(),
+    )
+    .await?;
+    info!("buildID: {}", build_id);
+    info!("templateID: {}", template_id);
+    report.template_id = Some(template_id.clone());
+    report.build_id = Some(build_id.clone());
+
+    report.finish(stage);
+
+    let stage = report.begin("push");
+    create_ecr_repo_if_needed(&ecr_client, &template_id).await?;
+
+    // Fetch credentials again so a long local build cannot leave us with an expired token
+    let auth = ecr_auth.get().await?;
     let ecr_target_tag = format!(
-        "{}/e2bdev/base/{}:{}",
-        registry.trim_start_matches("https://"),
+        "{}/{}/{}:{}",
+        auth.registry.trim_start_matches("https://"),
+        ECR_REPO_PREFIX,
         template_id,
         build_id
     );
 
     tag_image(&base_image, &ecr_target_tag).await?;
-    push_image(&ecr_target_tag, &docker_creds).await?;
+    push_image(&ecr_target_tag, &auth.credentials).await?;
     info!("Pushed base image to ECR: {}", ecr_target_tag);
+    report.image = Some(ecr_target_tag.clone());
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    match ecr_image_digest(&ecr_client, &repo_name, &build_id).await {
+        Ok(digest) => report.image_digest = digest,
+        Err(err) => warn!("Could not read the pushed image digest: {:#}", err),
+    }
+    if let Some(git) = &report.git {
+        if let Err(err) = add_image_tag(&ecr_client, &repo_name, &build_id, &git.tag()).await {
+            warn!("Could not tag the image with its git commit: {:#}", err);
+        }
+    }
+    report.finish(stage);
+
+    // Start a build on every replica cluster once the primary image is pushed;
+    // failures are reported per region
+    let stage = (!replicas.is_empty()).then(|| report.begin("replicate"));
+    let mut replica_builds = Vec::new();
+    let mut results = Vec::new();
+    for replica in replicas {
+        let token = match replica.e2b_access_token.as_deref() {
+            Some(raw) => match resolve_secret(raw, Some(&replica.aws_region)).await {
+                Ok(token) => format_bearer_token(&token),
+                Err(err) => {
+                    results.push(RegionResult {
+                        aws_region: replica.aws_region,
+                        e2b_domain: replica.e2b_domain,
+                        template_id: String::new(),
+                        build_id: String::new(),
+                        outcome: Err(err),
+                    });
+                    continue;
+                }
+            },
+            None => e2b_access_token.clone(),
+        };
+        // Template IDs differ between clusters, so the replica's template is found by alias
+        let replica_template_id =
+            find_current_build(&replica.e2b_domain, &token, None, resolved_alias.as_deref())
+                .await
+                .map(|t| t.template_id);
+        match &replica_template_id {
+            Some(tid) => info!(
+                "Creating build of template {} on replica cluster {} ({})",
+                tid, replica.e2b_domain, replica.aws_region
+            ),
+            None => info!(
+                "Creating build on replica cluster {} ({})",
+                replica.e2b_domain, replica.aws_region
+            ),
+        }
+        match build_template(
+            &replica.e2b_domain,
+            &token,
+            &dockerfile_content,
+            resolved_memory_mb,
+            resolved_cpu,
+            resolved_start_cmd.clone(),
+            resolved_ready_cmd.clone(),
+            resolved_alias.clone(),
+            replica_template_id,
+        )
+        .await
+        {
+            Ok((replica_build_id, replica_template_id)) => replica_builds.push(ClusterBuild {
+                aws_region: replica.aws_region,
+                e2b_domain: replica.e2b_domain,
+                access_token: token,
+                template_id: replica_template_id,
+                build_id: replica_build_id,
+            }),
+            Err(err) => results.push(RegionResult {
+                aws_region: replica.aws_region,
+                e2b_domain: replica.e2b_domain,
+                template_id: String::new(),
+                build_id: String::new(),
+                outcome: Err(err),
+            }),
+        }
+    }
 
-    notify_build_complete(&e2b_domain, &e2b_access_token, &template_id, &build_id).await?;
+    let replication_mode = user_cfg
+        .as_ref()
+        .and_then(|c| c.aws.as_ref().and_then(|a| a.replication))
+        .unwrap_or_default();
+    if replication_mode == ReplicationMode::EcrReplication && !replica_builds.is_empty() {
+        let regions: Vec<String> = replica_builds
+            .iter()
+            .map(|b| b.aws_region.clone())
+            .collect();
+        ensure_replication_rule(&ecr_client, &aws_account_id, &regions).await?;
+    }
 
-    poll_build_status_until_done(&e2b_domain, &e2b_access_token, &template_id, &build_id).await?;
-    info!("Build completed");
+    // Deliver the image to each replica region before its build is notified
+    let mut ready_builds = Vec::new();
+    for replica in replica_builds {
+        let delivered = match replication_mode {
+            ReplicationMode::Copy => {
+                copy_to_region(&base_image, &replica, &aws_account_id, use_token_cache).await
+            }
+            ReplicationMode::EcrReplication => {
+                replicate_from_primary(&base_image, &replica, &ecr_client, &ecr_auth).await
+            }
+        };
+        match delivered {
+            Ok(()) => ready_builds.push(replica),
+            Err(err) => results.push(replica.into_result(Err(err))),
+        }
+    }
+
+    if let Some(stage) = stage {
+        report.finish(stage);
+    }
+
+    let stage = report.begin("finish");
+    let primary = ClusterBuild {
+        aws_region: aws_region.clone(),
+        e2b_domain,
+        access_token: e2b_access_token,
+        template_id,
+        build_id,
+    };
+    if ready_builds.is_empty() && results.is_empty() {
+        let outcome = primary.finish().await;
+        report.finish(stage);
+        outcome?;
+        record_fingerprint(&ecr_client, &primary, &fingerprint).await;
+        report.tags = apply_extra_tags(
+            &ecr_client,
+            &primary.template_id,
+            &primary.build_id,
+            &extra_tags,
+        )
+        .await;
+        report.status = BuildStatus::Ready;
+        info!("Build completed");
+        return Ok(());
+    }
 
+    let outcome = primary.finish().await;
+    if outcome.is_ok() {
+        record_fingerprint(&ecr_client, &primary, &fingerprint).await;
+        report.tags = apply_extra_tags(
+            &ecr_client,
+            &primary.template_id,
+            &primary.build_id,
+            &extra_tags,
+        )
+        .await;
+    }
+    results.insert(0, primary.into_result(outcome));
+    for replica in ready_builds {
+        let outcome = replica.finish().await;
+        results.push(replica.into_result(outcome));
+    }
+    report.finish(stage);
+    report.replicas = results
+        .iter()
+        .skip(1)
+        .map(RegionResult::to_report)
+        .collect();
+    report_region_results(&results)?;
+    report.status = BuildStatus::Ready;
     Ok(())
 }
 
-/// Determine build method based on command line arguments and configuration
+/// Extra tags from `aws_e2b.toml` and `--tag`, with `{date}` expanded and duplicates removed
+fn resolve_extra_tags(
+    config_tags: &[String],
+    flag_tags: &[String],
+    date: &str,
+) -> Result<Vec<String>> {
+    let mut tags: Vec<String> = Vec::new();
+    for raw in config_tags.iter().chain(flag_tags) {
+        let tag = raw.replace("{date}", date);
+        let valid = tag.len() <= 128
+            && tag
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !valid {
+            return Err(anyhow!(
+                "Invalid image tag `{}`: use up to 128 letters, digits, `.`, `_` and `-`",
+                tag
+            ));
+        }
+        if [FINGERPRINT_TAG_PREFIX, GIT_TAG_PREFIX, PROMOTED_TAG_PREFIX]
+            .iter()
+            .any(|prefix| tag.starts_with(prefix))
+        {
+            return Err(anyhow!(
+                "Image tag `{}` starts with a prefix aws_e2b uses for its own tags",
+                tag
+            ));
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Fingerprint the inputs of a build without building or pulling anything
+#[allow(clippy::too_many_arguments)]
+async fn build_fingerprint(
+    build_type: &BuildType,
+    dockerfile_content: &str,
+    dockerfile_path: Option<&Path>,
+    base_image: Option<&str>,
+    ecr_client: &ecr::Client,
+    registry: &str,
+    memory_mb: u32,
+    cpu_count: u32,
+    start_cmd: Option<&str>,
+    ready_cmd: Option<&str>,
+) -> Result<String> {
+    // Tags can move, so identify base images by their registry digest
+    let references = match build_type {
+        BuildType::Dockerfile => dockerfile_base_images(dockerfile_content),
+        BuildType::EcrImage | BuildType::Default => {
+            base_image.map(str::to_string).into_iter().collect()
+        }
+    };
+    let mut base_images = Vec::new();
+    for reference in references {
+        base_images.push(base_image_digest(&reference, ecr_client, registry).await);
+    }
+    let context_dir = match build_type {
+        BuildType::Dockerfile => dockerfile_path.map(|p| {
+            p.parent()
+                .filter(|d| !d.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf()
+        }),
+        _ => None,
+    };
+    // Hashing the build context reads every file, so it runs on the blocking
+    // thread pool to keep concurrent builds polling
+    let dockerfile = dockerfile_content.to_string();
+    let start_cmd = start_cmd.map(str::to_string);
+    let ready_cmd = ready_cmd.map(str::to_string);
+    tokio::task::spawn_blocking(move || {
+        fingerprint(&FingerprintInputs {
+            dockerfile: &dockerfile,
+            context_dir: context_dir.as_deref(),
+            base_images,
+            memory_mb,
+            cpu_count,
+            start_cmd: start_cmd.as_deref(),
+            ready_cmd: ready_cmd.as_deref(),
+        })
+    })
+    .await
+    .context("fingerprint task failed")?
+}
+
+/// Registry digest of a base image, looked up without pulling it
+///
+/// Images in the account's own ECR registry are resolved through the ECR API and
+/// others with `docker buildx imagetools inspect`. A reference that cannot be
+/// resolved is used as-is.
+async fn base_image_digest(reference: &str, ecr_client: &ecr::Client, registry: &str) -> String {
+    if reference.contains('$') {
+        return reference.to_string();
+    }
+    if let Some((_, digest)) = reference.split_once('@') {
+        return digest.to_string();
+    }
+    let in_registry = reference
+        .strip_prefix(registry)
+        .and_then(|r| r.strip_prefix('/'))
+        .and_then(|r| r.rsplit_once(':'));
+    let digest = match in_registry {
+        Some((repo, tag)) => ecr_image_digest(ecr_client, repo, tag)
+            .await
+            .and_then(|d| d.ok_or_else(|| anyhow!("{}:{} was not found in ECR", repo, tag))),
+        None => remote_image_digest(reference).await,
+    };
+    digest.unwrap_or_else(|err| {
+        warn!("Could not resolve the digest of {}: {:#}", reference, err);
+        reference.to_string()
+    })
+}
+
+/// Latest ready build of the template selected by ID or alias, if it already exists
+///
+/// A failed lookup is logged and treated as no build; use [`lookup_current_build`]
+/// where the difference matters.
+pub async fn find_current_build(
+    e2b_domain: &str,
+    access_token: &str,
+    template_id: Option<&str>,
+    alias: Option<&str>,
+) -> Option<TemplateInfo> {
+    lookup_current_build(e2b_domain, access_token, template_id, alias)
+        .await
+        .unwrap_or_else(|err| {
+            warn!("Could not look up the current build: {:#}", err);
+            None
+        })
+}
+
+/// Latest ready build of the template selected by ID or alias, failing when templates cannot be listed
+pub async fn lookup_current_build(
+    e2b_domain: &str,
+    access_token: &str,
+    template_id: Option<&str>,
+    alias: Option<&str>,
+) -> Result<Option<TemplateInfo>> {
+    if template_id.is_none() && alias.is_none() {
+        return Ok(None);
+    }
+    let templates = list_templates(e2b_domain, access_token).await?;
+    Ok(templates.into_iter().find(|t| {
+        !t.build_id.is_empty()
+            && (Some(t.template_id.as_str()) == template_id
+                || alias.is_some_and(|a| t.aliases.iter().flatten().any(|x| x == a)))
+    }))
+}
+
+/// Tag the pushed image with its fingerprint so identical rebuilds can be skipped
+async fn record_fingerprint(ecr_client: &ecr::Client, built: &ClusterBuild, fingerprint: &str) {
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, built.template_id);
+    if let Err(err) = add_image_tag(
+        ecr_client,
+        &repo_name,
+        &built.build_id,
+        &fingerprint_tag(fingerprint),
+    )
+    .await
+    {
+        warn!("Could not record the build fingerprint: {:#}", err);
+    }
+}
+
+/// Add the extra tags to the image of a ready build, returning the tags that were added
+///
+/// Only ready builds are tagged, so a tag such as `latest` never points at a
+/// failed build. A tag that cannot be added is logged and left out.
+async fn apply_extra_tags(
+    ecr_client: &ecr::Client,
+    template_id: &str,
+    build_id: &str,
+    tags: &[String],
+) -> Vec<String> {
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    let mut applied = Vec::new();
+    for tag in tags {
+        match add_image_tag(ecr_client, &repo_name, build_id, tag).await {
+            Ok(()) => {
+                info!("Tagged image as {}", tag);
+                applied.push(tag.clone());
+            }
+            Err(err) => warn!("Could not tag the image as {}: {:#}", tag, err),
+        }
+    }
+    applied
+}
+
+/// A template build started on one e2b cluster
+pub struct ClusterBuild {
+    pub aws_region: String,
+    pub e2b_domain: String,
+    pub access_token: String,
+    pub template_id: String,
+    pub build_id: String,
+}
+
+impl ClusterBuild {
+    /// Notify the cluster that the image is pushed and wait for the build to finish
+    pub async fn finish(&self) -> Result<()> {
+        notify_build_complete(
+            &self.e2b_domain,
+            &self.access_token,
+            &self.template_id,
+            &self.build_id,
+        )
+        .await?;
+        cancellable(poll_build_status_until_done(
+            &self.e2b_domain,
+            &self.access_token,
+            &self.template_id,
+            &self.build_id,
+        ))
+        .await
+    }
+
+    fn into_result(self, outcome: Result<()>) -> RegionResult {
+        RegionResult {
+            aws_region: self.aws_region,
+            e2b_domain: self.e2b_domain,
+            template_id: self.template_id,
+            build_id: self.build_id,
+            outcome,
+        }
+    }
+}
+
+/// Final outcome of the build in one region
+struct RegionResult {
+    aws_region: String,
+    e2b_domain: String,
+    template_id: String,
+    build_id: String,
+    outcome: Result<()>,
+}
+
+impl RegionResult {
+    fn to_report(&self) -> ReplicaReport {
+        let non_empty = |s: &String| Some(s.clone()).filter(|s| !s.is_empty());
+        ReplicaReport {
+            aws_region: self.aws_region.clone(),
+            e2b_domain: self.e2b_domain.clone(),
+            template_id: non_empty(&self.template_id),
+            build_id: non_empty(&self.build_id),
+            status: if self.outcome.is_ok() {
+                BuildStatus::Ready
+            } else {
+                BuildStatus::Failed
+            },
+            error: self.outcome.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+/// Push the base image to the registry of a replica region
+async fn copy_to_region(
+    base_image: &str,
+    replica: &ClusterBuild,
+    aws_account_id: &str,
+    use_token_cache: bool,
+) -> Result<()> {
+    let shared_config = load_aws_config(&replica.aws_region).await;
+    let ecr_client = ecr::Client::new(&shared_config);
+    create_ecr_repo_if_needed(&ecr_client, &replica.template_id).await?;
+    let auth = EcrAuthProvider::new(
+        ecr_client,
+        aws_account_id,
+        &replica.aws_region,
+        use_token_cache,
+    )
+    .get()
+    .await?;
+    let target = format!(
+        "{}/{}/{}:{}",
+        auth.registry.trim_start_matches("https://"),
+        ECR_REPO_PREFIX,
+        replica.template_id,
+        replica.build_id
+    );
+    tag_image(base_image, &target).await?;
+    push_image(&target, &auth.credentials).await?;
+    info!("Pushed base image to ECR: {}", target);
+    Ok(())
+}
+
+/// Push the replica's tag to the primary registry and wait for ECR to replicate it
+async fn replicate_from_primary(
+    base_image: &str,
+    replica: &ClusterBuild,
+    ecr_client: &ecr::Client,
+    ecr_auth: &EcrAuthProvider,
+) -> Result<()> {
+    create_ecr_repo_if_needed(ecr_client, &replica.template_id).await?;
+    let auth = ecr_auth.get().await?;
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, replica.template_id);
+    let target = format!(
+        "{}/{}:{}",
+        auth.registry.trim_start_matches("https://"),
+        repo_name,
+        replica.build_id
+    );
+    tag_image(base_image, &target).await?;
+    push_image(&target, &auth.credentials).await?;
+    info!(
+        "Pushed {} for replication to {}",
+        target, replica.aws_region
+    );
+    wait_for_replication(
+        ecr_client,
+        &repo_name,
+        &replica.build_id,
+        &replica.aws_region,
+    )
+    .await
+}
+
+/// Log the outcome per region and fail if any region failed
+fn report_region_results(results: &[RegionResult]) -> Result<()> {
+    info!("Build results per region:");
+    for r in results {
+        match &r.outcome {
+            Ok(()) => info!(
+                "  {} ({}): ready, templateID {}, buildID {}",
+                r.aws_region, r.e2b_domain, r.template_id, r.build_id
+            ),
+            Err(err) => error!("  {} ({}): failed: {:#}", r.aws_region, r.e2b_domain, err),
+        }
+    }
+    let failed = results.iter().filter(|r| r.outcome.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "build failed in {} of {} regions",
+            failed,
+            results.len()
+        ));
+    }
+    info!("Build completed");
+    Ok(())
+}
+
+/// Dockerfile `aws_e2b.toml` points at, relative to the directory of the file
+pub fn resolve_toml_dockerfile(path: &str, toml_base_dir: Option<&Path>) -> PathBuf {
+    let raw = Path::new(path);
+    match toml_base_dir {
+        Some(base) if raw.is_relative() => base.join(raw),
+        _ => raw.to_path_buf(),
+    }
+}
+
+/// Image a template is built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    /// Dockerfile built locally
+    Dockerfile(PathBuf),
+    /// Existing image in ECR
+    EcrImage(String),
+    /// Image pulled and pushed unchanged
+    BaseImage(String),
+}
+
+impl fmt::Display for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageSource::Dockerfile(path) => write!(f, "dockerfile {}", path.display()),
+            ImageSource::EcrImage(image) => write!(f, "ecr-image {}", image),
+            ImageSource::BaseImage(image) => write!(f, "base image {}", image),
+        }
+    }
+}
+
+/// Template settings: command line > `aws_e2b.toml` > default
+///
+/// `template build` builds with these settings and `config show` prints them.
+#[derive(Debug)]
+pub struct TemplateSettings {
+    pub memory_mb: Sourced<u32>,
+    pub cpu_count: Sourced<u32>,
+    pub start_cmd: Option<Sourced<String>>,
+    pub ready_cmd: Option<Sourced<String>>,
+    pub alias: Option<Sourced<String>>,
+    pub template_id: Option<Sourced<String>>,
+    pub image: Sourced<ImageSource>,
+    /// `[docker].tags` before `--tag` is added
+    pub tags: Vec<String>,
+}
+
+impl TemplateSettings {
+    /// Settings of `template`, or of a file without named templates when `None`
+    pub fn resolve(
+        args: &BuildArgs,
+        e2b_cfg: &E2bConfigToml,
+        e2b_path: Option<&Path>,
+        template: Option<&str>,
+    ) -> Result<Self> {
+        if args.docker.docker_file.is_some() && args.docker.ecr_image.is_some() {
+            return Err(anyhow!(
+                "The `--docker-file` and `--ecr-image` options cannot be used together",
+            ));
+        }
+        let resolved = e2b_cfg.select_template(template)?;
+        // Keys set in the template's own table rather than inherited from the top level
+        let own = template
+            .and_then(|name| e2b_cfg.templates.as_ref()?.get(name).cloned())
+            .unwrap_or_default();
+        let own_e2b = own.e2b.unwrap_or_default();
+        let own_docker = own.docker.is_some_and(|d| {
+            d.dockerfile.is_some() || d.ecr_image.is_some() || d.docker_image.is_some()
+        });
+        let toml_key = |section: &str, key: &str, in_template: bool| Source::File {
+            path: e2b_path.map(Path::to_path_buf).unwrap_or_default(),
+            key: match template {
+                Some(name) if in_template => format!("[templates.{}.{}].{}", name, section, key),
+                _ => format!("[{}].{}", section, key),
+            },
+        };
+        let e2b = resolved.e2b.unwrap_or_default();
+        let docker = resolved.docker.unwrap_or_default();
+
+        let image = pick([
+            (
+                args.docker.docker_file.clone().map(ImageSource::Dockerfile),
+                Source::Flag("--docker-file"),
+            ),
+            (
+                args.docker.ecr_image.clone().map(ImageSource::EcrImage),
+                Source::Flag("--ecr-image"),
+            ),
+            (
+                docker.ecr_image.clone().map(ImageSource::EcrImage),
+                toml_key("docker", "ecr-image", own_docker),
+            ),
+            (
+                docker.dockerfile.as_deref().map(|path| {
+                    ImageSource::Dockerfile(resolve_toml_dockerfile(
+                        path,
+                        e2b_path.and_then(Path::parent),
+                    ))
+                }),
+                toml_key("docker", "dockerfile", own_docker),
+            ),
+            (
+                args.docker.base_image.clone().map(ImageSource::BaseImage),
+                Source::Flag("--base-image"),
+            ),
+            (
+                docker.docker_image.clone().map(ImageSource::BaseImage),
+                toml_key("docker", "dockerimage", own_docker),
+            ),
+        ])
+        .unwrap_or_else(|| {
+            Sourced::default_value(ImageSource::BaseImage(DEFAULT_IMAGE.to_string()))
+        });
+
+        Ok(Self {
+            memory_mb: pick([
+                (args.e2b.memory_mb, Source::Flag("--memory-mb")),
+                (
+                    e2b.memory_mb,
+                    toml_key("e2b", "memory_mb", own_e2b.memory_mb.is_some()),
+                ),
+            ])
+            .unwrap_or_else(|| Sourced::default_value(DEFAULT_MEMORY_MB)),
+            cpu_count: pick([
+                (args.e2b.cpu_count, Source::Flag("--cpu-count")),
+                (
+                    e2b.cpu_count,
+                    toml_key("e2b", "cpu_count", own_e2b.cpu_count.is_some()),
+                ),
+            ])
+            .unwrap_or_else(|| Sourced::default_value(DEFAULT_CPU_COUNT)),
+            start_cmd: pick([
+                (args.e2b.start_cmd.clone(), Source::Flag("--start-cmd")),
+                (
+                    e2b.start_cmd,
+                    toml_key("e2b", "start_cmd", own_e2b.start_cmd.is_some()),
+                ),
+            ]),
+            ready_cmd: pick([
+                (args.e2b.ready_cmd.clone(), Source::Flag("--ready-cmd")),
+                (
+                    e2b.ready_cmd,
+                    toml_key("e2b", "ready_cmd", own_e2b.ready_cmd.is_some()),
+                ),
+            ]),
+            alias: pick([
+                (args.e2b.alias.clone(), Source::Flag("--alias")),
+                (e2b.alias, toml_key("e2b", "alias", own_e2b.alias.is_some())),
+            ]),
+            template_id: pick([
+                (args.e2b.template_id.clone(), Source::Flag("--template-id")),
+                (
+                    e2b.template_id,
+                    toml_key("e2b", "template_id", own_e2b.template_id.is_some()),
+                ),
+            ]),
+            image,
+            tags: docker.tags.unwrap_or_default(),
+        })
+    }
+}
+
+/// Build type, Dockerfile content sent to e2b, image to pull and Dockerfile to build
 fn resolve_build_input(
-    args: &BuildArgs,
-    toml_dockerfile_path: Option<&str>,
-    toml_ecr_image: Option<&str>,
-    toml_docker_image: Option<&str>,
-    toml_base_dir: Option<&Path>,
+    image: &Sourced<ImageSource>,
 ) -> Result<(BuildType, String, Option<String>, Option<PathBuf>)> {
-    match (&args.docker.docker_file, &args.docker.ecr_image) {
-        (Some(_), Some(_)) => Err(anyhow!(
-            "The `--docker-file` and `--ecr-image` options cannot be used together",
-        )),
-        (Some(path), None) => {
+    match &image.value {
+        ImageSource::Dockerfile(path) => {
             let content = fs::read_to_string(path)
                 .with_context(|| format!("failed to read Dockerfile: {}", path.display()))?;
-            info!("Building with provided Dockerfile content");
+            info!("Building Dockerfile {} ({})", path.display(), image.source);
             Ok((BuildType::Dockerfile, content, None, Some(path.clone())))
         }
-        (None, Some(image)) => {
-            info!("Building with provided ECR image: {}", image);
+        ImageSource::EcrImage(img) => {
+            info!("Building with ECR image {} ({})", img, image.source);
             Ok((
                 BuildType::EcrImage,
-                format!("FROM {}", image),
-                Some(image.clone()),
+                format!("FROM {}", img),
+                Some(img.clone()),
                 None,
             ))
         }
-        (None, None) => {
-            if let Some(image) = toml_ecr_image {
-                info!("Using ecr-image from aws_e2b.toml: {}", image);
-                return Ok((
-                    BuildType::EcrImage,
-                    format!("FROM {}", image),
-                    Some(image.to_string()),
-                    None,
-                ));
-            }
-            if let Some(dockerfile_path) = toml_dockerfile_path {
-                let raw = Path::new(dockerfile_path);
-                let path = if raw.is_relative() {
-                    if let Some(base) = toml_base_dir {
-                        base.join(raw)
-                    } else {
-                        raw.to_path_buf()
-                    }
-                } else {
-                    raw.to_path_buf()
-                };
-                let content = fs::read_to_string(&path).with_context(|| {
-                    format!(
-                        "failed to read Dockerfile from configuration: {}",
-                        path.display()
-                    )
-                })?;
-                info!("Reading Dockerfile from aws_e2b.toml: {}", path.display());
-                return Ok((BuildType::Dockerfile, content, None, Some(path)));
-            }
-            if let Some(img) = toml_docker_image {
-                info!(
-                    "Using dockerimage from aws_e2b.toml as default base image: {}",
-                    img
-                );
-                let dockerfile = format!("FROM {}", img);
-                return Ok((BuildType::Default, dockerfile, None, None));
-            }
-            let default_dockerfile = format!("FROM {}", DEFAULT_IMAGE);
-            info!("Using default base image: {}", DEFAULT_IMAGE);
-            Ok((BuildType::Default, default_dockerfile, None, None))
+        ImageSource::BaseImage(img) => {
+            info!("Building with base image {} ({})", img, image.source);
+            Ok((
+                BuildType::Default,
+                format!("FROM {}", img),
+                Some(img.clone()),
+                None,
+            ))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_summary_aligns_columns() {
+        let mut python = BuildReport::new(Some("python".to_string()));
+        python.status = BuildStatus::Ready;
+        python.template_id = Some("tmpl123".to_string());
+        python.build_id = Some("b1".to_string());
+        let results = vec![
+            TemplateResult {
+                name: "python".to_string(),
+                report: python,
+                outcome: Ok(()),
+            },
+            TemplateResult::failed("node".to_string(), anyhow!("boom")),
+        ];
+        assert_eq!(
+            format_summary(&results),
+            "TEMPLATE  STATUS  TEMPLATE ID  BUILD ID\n\
+             python    ready   tmpl123      b1\n\
+             node      failed  -            -"
+        );
+    }
+
+    #[test]
+    fn resolve_extra_tags_expands_dates_and_rejects_bad_tags() {
+        let tags = resolve_extra_tags(
+            &["latest".to_string(), "nightly-{date}".to_string()],
+            &["v1.4.2".to_string(), "latest".to_string()],
+            "20261018",
+        )
+        .unwrap();
+        assert_eq!(tags, vec!["latest", "nightly-20261018", "v1.4.2"]);
+        assert!(resolve_extra_tags(&[], &["bad tag".to_string()], "20261018").is_err());
+        assert!(resolve_extra_tags(&[], &["fp-abc".to_string()], "20261018").is_err());
+    }
+
+    #[tokio::test]
+    async fn schedule_builds_waits_for_and_skips_after_dependencies() {
+        let deps = BTreeMap::from([
+            ("base".to_string(), vec![]),
+            ("child".to_string(), vec!["base".to_string()]),
+            ("broken".to_string(), vec![]),
+            ("orphan".to_string(), vec!["broken".to_string()]),
+            ("grandchild".to_string(), vec!["orphan".to_string()]),
+        ]);
+        let started = std::sync::Mutex::new(Vec::new());
+        let names = ["grandchild", "child", "orphan", "base", "broken"]
+            .map(String::from)
+            .to_vec();
+        let results = schedule_builds(names, &deps, 2, |name| {
+            started.lock().unwrap().push(name.clone());
+            async move {
+                if name == "broken" {
+                    return TemplateResult::failed(name, anyhow!("boom"));
+                }
+                TemplateResult {
+                    report: BuildReport::new(Some(name.clone())),
+                    name,
+                    outcome: Ok(()),
+                }
+            }
+        })
+        .await;
+        assert_eq!(*started.lock().unwrap(), vec!["base", "broken", "child"]);
+        let outcomes: Vec<(&str, bool)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.outcome.is_ok()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("grandchild", false),
+                ("child", true),
+                ("orphan", false),
+                ("base", true),
+                ("broken", false)
+            ]
+        );
+    }
+}
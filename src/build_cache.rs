@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use aws_sdk_ecr as ecr;
+use aws_sdk_ecr::operation::describe_images::DescribeImagesError;
+use aws_sdk_ecr::types::ImageIdentifier;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+
+use crate::aws_utils::{ecr_error, ECR_REPO_PREFIX};
+
+/// Prefix of the ECR tag recording the fingerprint of a ready build
+pub const FINGERPRINT_TAG_PREFIX: &str = "fp-";
+
+/// Everything that determines the content of a template build
+#[derive(Debug, Default)]
+pub struct FingerprintInputs<'a> {
+    pub dockerfile: &'a str,
+    /// Build context directory for Dockerfile builds
+    pub context_dir: Option<&'a Path>,
+    /// Digests (or references, when no digest is known) of the base images
+    pub base_images: Vec<String>,
+    pub memory_mb: u32,
+    pub cpu_count: u32,
+    pub start_cmd: Option<&'a str>,
+    pub ready_cmd: Option<&'a str>,
+}
+
+/// Hash the build inputs into a hex fingerprint
+pub fn fingerprint(inputs: &FingerprintInputs) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut field = |name: &str, value: &[u8]| {
+        hasher.update(name.as_bytes());
+        hasher.update((value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    };
+    field("version", b"1");
+    field("dockerfile", inputs.dockerfile.as_bytes());
+    for image in &inputs.base_images {
+        field("base_image", image.as_bytes());
+    }
+    field("memory_mb", &inputs.memory_mb.to_le_bytes());
+    field("cpu_count", &inputs.cpu_count.to_le_bytes());
+    field("start_cmd", inputs.start_cmd.unwrap_or_default().as_bytes());
+    field("ready_cmd", inputs.ready_cmd.unwrap_or_default().as_bytes());
+    if let Some(dir) = inputs.context_dir {
+        for path in context_files(dir)? {
+            let content =
+                fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            let relative = path.strip_prefix(dir).unwrap_or(&path);
+            field("path", relative.to_string_lossy().as_bytes());
+            field("content", &Sha256::digest(&content));
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Files of a docker build context, excluding those matched by its `.dockerignore`, sorted
+///
+/// `.git` directories are always left out: git rewrites them on every status or
+/// fetch, which would change the fingerprint without changing the image.
+pub fn context_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let ignore = dockerignore(dir)?;
+    let mut files = Vec::new();
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            entry.file_name() != ".git"
+                && !ignore
+                    .matched_path_or_any_parents(entry.path(), is_dir)
+                    .is_ignore()
+        })
+        .build();
+    for entry in walker {
+        let entry = entry.with_context(|| format!("failed to walk {}", dir.display()))?;
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Matcher for `<dir>/.dockerignore`
+///
+/// Docker anchors every pattern at the context root, unlike `.gitignore` where a
+/// bare name matches at any depth, so patterns are rooted before they are added.
+pub fn dockerignore(dir: &Path) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let path = dir.join(".dockerignore");
+    if path.is_file() {
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        for line in raw.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negate, pattern) = match line.strip_prefix('!') {
+                Some(rest) => ("!", rest),
+                None => ("", line),
+            };
+            let pattern = pattern.trim_start_matches("./").trim_start_matches('/');
+            builder
+                .add_line(None, &format!("{}/{}", negate, pattern))
+                .with_context(|| format!("invalid pattern in {}: {}", path.display(), line))?;
+        }
+    }
+    builder
+        .build()
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// External images named in `FROM` instructions, skipping `scratch` and earlier stages
+pub fn dockerfile_base_images(dockerfile: &str) -> Vec<String> {
+    let mut stages: Vec<String> = Vec::new();
+    let mut images = Vec::new();
+    for line in dockerfile.lines().map(str::trim) {
+        let mut words = line.split_whitespace();
+        if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("FROM")) {
+            continue;
+        }
+        let mut words = words.skip_while(|w| w.starts_with("--"));
+        let Some(image) = words.next() else {
+            continue;
+        };
+        let is_stage = stages.iter().any(|s| s.eq_ignore_ascii_case(image));
+        if !is_stage && !image.eq_ignore_ascii_case("scratch") {
+            images.push(image.to_string());
+        }
+        if words.next().is_some_and(|w| w.eq_ignore_ascii_case("AS")) {
+            if let Some(stage) = words.next() {
+                stages.push(stage.to_string());
+            }
+        }
+    }
+    images
+}
+
+/// ECR tag recording a fingerprint
+pub fn fingerprint_tag(fingerprint: &str) -> String {
+    format!("{}{}", FINGERPRINT_TAG_PREFIX, fingerprint)
+}
+
+/// Whether the image of `build_id` in the template repository carries the fingerprint tag
+pub async fn build_has_fingerprint(
+    ecr_client: &ecr::Client,
+    template_id: &str,
+    build_id: &str,
+    fingerprint: &str,
+) -> Result<bool> {
+    let repo_name = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    let resp = match ecr_client
+        .describe_images()
+        .repository_name(&repo_name)
+        .image_ids(
+            ImageIdentifier::builder()
+                .image_tag(fingerprint_tag(fingerprint))
+                .build(),
+        )
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(err) => {
+            // A missing repository or tag simply means there is no matching build
+            return match err.as_service_error() {
+                Some(DescribeImagesError::ImageNotFoundException(_))
+                | Some(DescribeImagesError::RepositoryNotFoundException(_)) => Ok(false),
+                _ => Err(ecr_error(&format!("describe images in {}", repo_name), err)),
+            };
+        }
+    };
+    Ok(resp
+        .image_details()
+        .iter()
+        .any(|d| d.image_tags().iter().any(|t| t == build_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dockerfile_base_images_skips_stages_and_scratch() {
+        let dockerfile = "\
+            FROM --platform=linux/amd64 python:3.12 AS builder\n\
+            RUN pip wheel .\n\
+            from builder as test\n\
+            FROM scratch\n\
+            FROM 123456789012.dkr.ecr.us-east-1.amazonaws.com/e2bdev/base/abc:build\n";
+        assert_eq!(
+            dockerfile_base_images(dockerfile),
+            vec![
+                "python:3.12",
+                "123456789012.dkr.ecr.us-east-1.amazonaws.com/e2bdev/base/abc:build"
+            ]
+        );
+    }
+
+    #[test]
+    fn fingerprint_covers_context_but_not_ignored_files() {
+        let dir = std::env::temp_dir().join(format!("aws_e2b_fp_{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("build")).unwrap();
+        fs::write(dir.join(".dockerignore"), "build\n*.log\n").unwrap();
+        fs::write(dir.join("src").join("app.py"), "print(1)").unwrap();
+        fs::write(dir.join("build").join("out.bin"), "x").unwrap();
+        fs::write(dir.join("debug.log"), "x").unwrap();
+        let inputs = |dockerfile| FingerprintInputs {
+            dockerfile,
+            context_dir: Some(&dir),
+            memory_mb: 1024,
+            cpu_count: 2,
+            ..Default::default()
+        };
+        let first = fingerprint(&inputs("FROM python")).unwrap();
+
+        fs::write(dir.join("debug.log"), "changed").unwrap();
+        fs::write(dir.join("build").join("out.bin"), "changed").unwrap();
+        assert_eq!(fingerprint(&inputs("FROM python")).unwrap(), first);
+        assert_ne!(fingerprint(&inputs("FROM node")).unwrap(), first);
+
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git").join("index"), "changed").unwrap();
+        assert_eq!(fingerprint(&inputs("FROM python")).unwrap(), first);
+
+        fs::write(dir.join("src").join("app.py"), "print(2)").unwrap();
+        assert_ne!(fingerprint(&inputs("FROM python")).unwrap(), first);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
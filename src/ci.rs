@@ -0,0 +1,331 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+
+use crate::args::CiMode;
+use crate::report::{BuildReport, BuildStatus};
+
+/// CI system the build runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiProvider {
+    GithubActions,
+    GitlabCi,
+}
+
+/// How CI integration behaves for this run
+#[derive(Debug, Clone, Copy)]
+struct CiContext {
+    provider: CiProvider,
+    /// Wrap build stages in collapsible log groups; off for concurrent builds
+    group_logs: bool,
+}
+
+static CI: OnceLock<CiContext> = OnceLock::new();
+
+/// Pick the CI provider from `--ci` and the environment variables the runners set
+pub fn detect(mode: CiMode, lookup: &dyn Fn(&str) -> Option<String>) -> Option<CiProvider> {
+    let is_set = |name: &str| lookup(name).is_some_and(|v| v == "true");
+    match mode {
+        CiMode::Off => None,
+        CiMode::Github => Some(CiProvider::GithubActions),
+        CiMode::Gitlab => Some(CiProvider::GitlabCi),
+        CiMode::Auto if is_set("GITHUB_ACTIONS") => Some(CiProvider::GithubActions),
+        CiMode::Auto if is_set("GITLAB_CI") => Some(CiProvider::GitlabCi),
+        CiMode::Auto => None,
+    }
+}
+
+/// Enable CI integration for this run; call once before building
+///
+/// Markers and annotations go to stderr, where both runners pick them up, so
+/// they do not mix with `--output json` on stdout.
+pub fn init(provider: Option<CiProvider>, group_logs: bool) {
+    if let Some(provider) = provider {
+        let _ = CI.set(CiContext {
+            provider,
+            group_logs,
+        });
+    }
+}
+
+/// Open a collapsible log group that is closed when the guard is dropped
+pub fn group(title: &str) -> Option<LogGroup> {
+    let ci = CI.get().filter(|c| c.group_logs)?;
+    let id = format!(
+        "aws_e2b_{}",
+        title
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>()
+    );
+    match ci.provider {
+        CiProvider::GithubActions => eprintln!("::group::{}", title),
+        CiProvider::GitlabCi => eprintln!(
+            "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+            chrono::Utc::now().timestamp(),
+            id,
+            title
+        ),
+    }
+    Some(LogGroup {
+        provider: ci.provider,
+        id,
+    })
+}
+
+/// An open log group
+pub struct LogGroup {
+    provider: CiProvider,
+    id: String,
+}
+
+impl Drop for LogGroup {
+    fn drop(&mut self) {
+        match self.provider {
+            CiProvider::GithubActions => eprintln!("::endgroup::"),
+            CiProvider::GitlabCi => eprintln!(
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                chrono::Utc::now().timestamp(),
+                self.id
+            ),
+        }
+    }
+}
+
+/// Dotenv file written on GitLab CI when neither `$AWS_E2B_DOTENV` nor `--result-file` is set
+const DEFAULT_DOTENV: &str = "aws_e2b.env";
+
+/// Publish build results to the CI system
+///
+/// GitHub Actions gets step outputs, a job summary and error annotations. GitLab CI
+/// gets the same outputs as a dotenv file for `artifacts:reports:dotenv` and the
+/// errors highlighted in the job log.
+pub fn publish(reports: &[&BuildReport], result_file: Option<&Path>) -> Result<()> {
+    let Some(ci) = CI.get() else {
+        return Ok(());
+    };
+    let outputs = step_outputs(reports);
+    match ci.provider {
+        CiProvider::GithubActions => {
+            for report in reports.iter().filter(|r| r.status == BuildStatus::Failed) {
+                eprintln!("{}", error_annotation(report));
+            }
+            if let Ok(path) = std::env::var("GITHUB_OUTPUT") {
+                let lines: String = outputs
+                    .iter()
+                    .map(|(key, value)| format!("{}={}\n", key, value))
+                    .collect();
+                append(Path::new(&path), &lines)?;
+            }
+            if let Ok(path) = std::env::var("GITHUB_STEP_SUMMARY") {
+                append(Path::new(&path), &job_summary(reports))?;
+            }
+        }
+        CiProvider::GitlabCi => {
+            for report in reports.iter().filter(|r| r.status == BuildStatus::Failed) {
+                eprintln!("{}", gitlab_error(report));
+            }
+            let path = dotenv_path(result_file, &|name| std::env::var(name).ok());
+            fs::write(&path, dotenv(&outputs))
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where the GitLab CI dotenv file is written
+///
+/// `$AWS_E2B_DOTENV` wins; otherwise it sits next to `--result-file` with an
+/// `.env` extension, or is `aws_e2b.env` in the working directory.
+pub fn dotenv_path(result_file: Option<&Path>, lookup: &dyn Fn(&str) -> Option<String>) -> PathBuf {
+    match (
+        lookup("AWS_E2B_DOTENV").filter(|p| !p.is_empty()),
+        result_file,
+    ) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(result_file)) => result_file.with_extension("env"),
+        (None, None) => PathBuf::from(DEFAULT_DOTENV),
+    }
+}
+
+fn append(path: &Path, content: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Step outputs as `(key, value)` pairs, prefixed with the template name when there are several
+fn step_outputs(reports: &[&BuildReport]) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    for report in reports {
+        let prefix = match (&report.name, reports.len()) {
+            (Some(name), n) if n > 1 => format!(
+                "{}_",
+                name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_")
+            ),
+            _ => String::new(),
+        };
+        for (key, value) in [
+            ("template_id", report.template_id.as_deref()),
+            ("build_id", report.build_id.as_deref()),
+            ("image_digest", report.image_digest.as_deref()),
+            ("status", Some(status_key(report.status))),
+        ] {
+            out.push((
+                format!("{}{}", prefix, key),
+                value.unwrap_or_default().to_string(),
+            ));
+        }
+    }
+    out
+}
+
+/// Dotenv lines for GitLab CI, with keys upper-cased like other CI variables
+fn dotenv(outputs: &[(String, String)]) -> String {
+    outputs
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key.to_uppercase(), value))
+        .collect()
+}
+
+/// Markdown table for `$GITHUB_STEP_SUMMARY`
+fn job_summary(reports: &[&BuildReport]) -> String {
+    let mut out = String::from(
+        "### aws_e2b template build\n\n\
+         | Template | Status | Template ID | Build ID | Image digest |\n\
+         | --- | --- | --- | --- | --- |\n",
+    );
+    for report in reports {
+        let cell = |v: Option<&str>| v.map(|v| format!("`{}`", v)).unwrap_or("-".into());
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {} |\n",
+            report
+                .name
+                .as_deref()
+                .or(report.alias.as_deref())
+                .unwrap_or("-"),
+            report.status.label(),
+            cell(report.template_id.as_deref()),
+            cell(report.build_id.as_deref()),
+            cell(report.image_digest.as_deref()),
+        ));
+    }
+    let errors: Vec<String> = reports
+        .iter()
+        .filter_map(|r| {
+            let name = r.name.as_deref().unwrap_or("template");
+            r.error.as_ref().map(|e| format!("- **{}**: {}\n", name, e))
+        })
+        .collect();
+    if !errors.is_empty() {
+        out.push_str("\n#### Errors\n\n");
+        out.extend(errors);
+    }
+    out.push('\n');
+    out
+}
+
+/// `::error` workflow command for a failed build
+fn error_annotation(report: &BuildReport) -> String {
+    let escape = |s: &str| {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let title = match &report.name {
+        Some(name) => format!("aws_e2b template {} failed", name),
+        None => "aws_e2b template build failed".to_string(),
+    };
+    format!(
+        "::error title={}::{}",
+        escape(&title).replace(',', "%2C").replace(':', "%3A"),
+        escape(report.error.as_deref().unwrap_or("unknown error"))
+    )
+}
+
+/// Failed build in the red `ERROR:` style the GitLab runner uses in job logs
+fn gitlab_error(report: &BuildReport) -> String {
+    let name = match &report.name {
+        Some(name) => format!("aws_e2b template {}", name),
+        None => "aws_e2b template build".to_string(),
+    };
+    format!(
+        "\x1b[31;1mERROR: {} failed: {}\x1b[0m",
+        name,
+        report.error.as_deref().unwrap_or("unknown error")
+    )
+}
+
+fn status_key(status: BuildStatus) -> &'static str {
+    match status {
+        BuildStatus::Ready => "ready",
+        BuildStatus::UpToDate => "up_to_date",
+        BuildStatus::Failed => "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_uses_runner_variables() {
+        let github = |name: &str| (name == "GITHUB_ACTIONS").then(|| "true".to_string());
+        assert_eq!(
+            detect(CiMode::Auto, &github),
+            Some(CiProvider::GithubActions)
+        );
+        assert_eq!(detect(CiMode::Off, &github), None);
+        assert_eq!(detect(CiMode::Auto, &|_| None), None);
+        assert_eq!(
+            detect(CiMode::Gitlab, &|_| None),
+            Some(CiProvider::GitlabCi)
+        );
+    }
+
+    #[test]
+    fn step_outputs_prefix_names_for_several_templates() {
+        let mut python = BuildReport::new(Some("python-3".to_string()));
+        python.status = BuildStatus::Ready;
+        python.template_id = Some("t1".to_string());
+        let single = step_outputs(&[&python]);
+        assert_eq!(single[0], ("template_id".to_string(), "t1".to_string()));
+        assert_eq!(single[1], ("build_id".to_string(), String::new()));
+        let node = BuildReport::new(Some("node".to_string()));
+        let several = dotenv(&step_outputs(&[&python, &node]));
+        assert!(several.contains("PYTHON_3_TEMPLATE_ID=t1\n"));
+        assert!(several.contains("NODE_STATUS=failed\n"));
+    }
+
+    #[test]
+    fn dotenv_path_follows_the_variable_and_result_file() {
+        let dotenv = |name: &str| (name == "AWS_E2B_DOTENV").then(|| "out/ci.env".to_string());
+        let result_file = Some(Path::new("out/results.json"));
+        assert_eq!(
+            dotenv_path(result_file, &dotenv),
+            PathBuf::from("out/ci.env")
+        );
+        assert_eq!(
+            dotenv_path(result_file, &|_| None),
+            PathBuf::from("out/results.env")
+        );
+        assert_eq!(dotenv_path(None, &|_| None), PathBuf::from("aws_e2b.env"));
+    }
+
+    #[test]
+    fn error_annotation_escapes_newlines() {
+        let mut report = BuildReport::new(Some("node".to_string()));
+        report.error = Some("push failed: denied\nretry".to_string());
+        assert_eq!(
+            error_annotation(&report),
+            "::error title=aws_e2b template node failed::push failed: denied%0Aretry"
+        );
+    }
+}
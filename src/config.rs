@@ -1,11 +1,16 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use std::{env, fs};
+use std::sync::OnceLock;
+use std::{env, fmt, fs};
 
 use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
 use serde::Deserialize;
+use toml_edit::{DocumentMut, Table};
 
 /// Configuration for the `[e2b]` section in `aws_e2b.toml`
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct E2bSection {
     #[serde(default)]
     pub memory_mb: Option<u32>,
@@ -22,7 +27,8 @@ pub struct E2bSection {
 }
 
 /// Configuration for the `[docker]` section in `aws_e2b.toml`
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DockerSection {
     #[serde(default)]
     pub dockerfile: Option<String>,
@@ -32,36 +38,145 @@ pub struct DockerSection {
         default,
         rename = "dockerimage",
         alias = "docker_image",
-        alias = "image"
+        alias = "image",
+        alias = "base-image"
     )]
     pub docker_image: Option<String>,
+    /// Extra ECR tags for the pushed image besides the build ID; `{date}` expands to the UTC date
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
 }
 
 /// Full structure of `aws_e2b.toml`
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct E2bConfigToml {
     #[serde(default)]
     pub e2b: Option<E2bSection>,
     #[serde(default)]
     pub docker: Option<DockerSection>,
+    /// Named templates in `[templates.<name>]`; the top-level sections act as shared defaults
+    #[serde(default)]
+    pub templates: Option<BTreeMap<String, TemplateSection>>,
+}
+
+/// A `[templates.<name>]` table with its own `[e2b]` and `[docker]` settings
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateSection {
+    /// Templates that must finish building first, e.g. because this one builds `FROM` their image
+    #[serde(default)]
+    pub depends_on: Option<Vec<String>>,
+    #[serde(default)]
+    pub e2b: Option<E2bSection>,
+    #[serde(default)]
+    pub docker: Option<DockerSection>,
 }
 
+/// Environment variable selecting the active profile
+pub const PROFILE_ENV: &str = "AWS_E2B_PROFILE";
+
+/// Profile selected with the global `--profile` flag
+static PROFILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Environment variable pointing at a user configuration file
+pub const USER_CONFIG_ENV: &str = "AWS_E2B_CONFIG";
+
+/// User configuration file given with the global `--user-config` flag
+static USER_CONFIG_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set by the global `--strict` flag: refuse insecure user configuration files
+static STRICT_PERMISSIONS: OnceLock<bool> = OnceLock::new();
+
+/// Ensures the insecure-permissions warning is printed only once per run
+static PERMISSION_WARNING: std::sync::Once = std::sync::Once::new();
+
 /// User-level configuration in `~/.aws_e2b/config.toml`
 #[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UserConfig {
     #[serde(default)]
     pub aws: Option<UserAwsSection>,
     #[serde(default)]
     pub e2b: Option<UserE2bSection>,
+    /// Additional e2b clusters in other AWS regions that receive the same template
+    #[serde(default)]
+    pub replicas: Option<Vec<ReplicaSection>>,
+    /// Profile used when neither `--profile` nor `AWS_E2B_PROFILE` is given
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Named contexts in `[profiles.<name>]` that override the top-level sections
+    #[serde(default)]
+    pub profiles: Option<BTreeMap<String, ProfileSection>>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// A `[profiles.<name>]` table with the same sections as the top level
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSection {
+    #[serde(default)]
+    pub aws: Option<UserAwsSection>,
+    #[serde(default)]
+    pub e2b: Option<UserE2bSection>,
+    #[serde(default)]
+    pub replicas: Option<Vec<ReplicaSection>>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UserAwsSection {
     #[serde(default)]
     pub aws_region: Option<String>,
+    /// Persist ECR tokens under `~/.aws_e2b/cache` so later runs can reuse them
+    #[serde(default)]
+    pub ecr_token_cache: Option<bool>,
+    /// Settings for pulling public base images through ECR pull-through cache rules
+    #[serde(default)]
+    pub pull_through_cache: Option<PullThroughCacheSection>,
+    /// How base images reach the registries of `[[replicas]]` regions
+    #[serde(default)]
+    pub replication: Option<ReplicationMode>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// Strategy for delivering base images to replica regions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplicationMode {
+    /// Push the image to each replica region's registry
+    #[default]
+    Copy,
+    /// Push once to the primary registry and let ECR replication rules copy it
+    EcrReplication,
+}
+
+/// An `[[replicas]]` entry: an e2b cluster served from another AWS region
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicaSection {
+    pub aws_region: String,
+    pub e2b_domain: String,
+    /// Access token for the replica cluster, defaulting to the primary token
+    #[serde(default)]
+    pub e2b_access_token: Option<String>,
+}
+
+/// Configuration for the `[aws.pull_through_cache]` section in `~/.aws_e2b/config.toml`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PullThroughCacheSection {
+    /// Route default base images through the cache without passing `--pull-through-cache`
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Secrets Manager ARN with Docker Hub credentials, required by ECR for Docker Hub rules
+    #[serde(default)]
+    pub docker_hub_credential_arn: Option<String>,
+    /// Secrets Manager ARN with GitHub Container Registry credentials
+    #[serde(default)]
+    pub ghcr_credential_arn: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UserE2bSection {
     #[serde(default, rename = "e2b_domain")]
     pub e2b_domain: Option<String>,
@@ -73,52 +188,1178 @@ pub struct UserE2bSection {
     /// e2b team identifier for the user
     #[serde(default, rename = "e2b_team_id")]
     pub e2b_team_id: Option<String>,
+    /// Largest CPU count the cluster accepts, checked before a build starts
+    #[serde(default)]
+    pub max_cpu_count: Option<u32>,
+    /// Largest memory size in megabytes the cluster accepts
+    #[serde(default)]
+    pub max_memory_mb: Option<u32>,
 }
 
-/// Load `aws_e2b.toml` and return the configuration and its directory
-pub fn load_e2b_toml(config_path: Option<&Path>) -> Result<(E2bConfigToml, Option<PathBuf>)> {
+/// Load `aws_e2b.toml` and return the configuration and the path it was read from
+///
+/// Without an explicit path the file is searched for from the current directory
+/// up to the repository root; `no_config` skips the search and uses defaults only.
+pub fn load_e2b_toml(
+    config_path: Option<&Path>,
+    no_config: bool,
+) -> Result<(E2bConfigToml, Option<PathBuf>)> {
     if let Some(p) = config_path {
         if p.exists() {
-            return parse_e2b_toml_file(p).map(|cfg| (cfg, p.parent().map(|d| d.to_path_buf())));
+            return parse_e2b_toml_file(p).map(|cfg| (cfg, Some(p.to_path_buf())));
         }
         return Err(anyhow!(
             "Specified configuration file does not exist: {}",
             p.display()
         ));
     }
+    if no_config {
+        return Ok((E2bConfigToml::default(), None));
+    }
 
     let cwd = std::env::current_dir()?;
-    let path = cwd.join("aws_e2b.toml");
-    if path.exists() {
+    if let Some(path) = find_e2b_toml(&cwd) {
+        info!("Using configuration {}", path.display());
         let cfg = parse_e2b_toml_file(&path)?;
-        return Ok((cfg, path.parent().map(|d| d.to_path_buf())));
+        return Ok((cfg, Some(path)));
     }
 
     Ok((E2bConfigToml::default(), None))
 }
 
+/// Find `aws_e2b.toml` in `start` or its parents up to the git repository root
+///
+/// Outside a repository only `start` itself is searched, so unrelated files in
+/// a home or temporary directory are not picked up.
+pub fn find_e2b_toml(start: &Path) -> Option<PathBuf> {
+    let in_repo = start.ancestors().any(|dir| dir.join(".git").exists());
+    for dir in start.ancestors() {
+        let path = dir.join("aws_e2b.toml");
+        if path.is_file() {
+            return Some(path);
+        }
+        if !in_repo || dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
 /// Parse `aws_e2b.toml` from disk
 pub fn parse_e2b_toml_file(path: &Path) -> Result<E2bConfigToml> {
     let raw = fs::read_to_string(path)
         .with_context(|| format!("failed to read TOML: {}", path.display()))?;
-    let cfg: E2bConfigToml = toml::from_str(&raw)
-        .with_context(|| format!("failed to parse TOML: {}", path.display()))?;
+    let mut cfg: E2bConfigToml =
+        parse_toml(&raw).with_context(|| format!("failed to parse TOML: {}", path.display()))?;
+    cfg.interpolate_env(&|name| env::var(name).ok())
+        .with_context(|| format!("failed to expand variables: {}", path.display()))?;
+    cfg.validate()
+        .with_context(|| format!("invalid configuration: {}", path.display()))?;
     Ok(cfg)
 }
 
-/// Read user configuration `~/.aws_e2b/config.toml`
+impl E2bConfigToml {
+    /// Names of the `[templates.<name>]` tables, in sorted order
+    pub fn template_names(&self) -> Vec<String> {
+        self.templates
+            .as_ref()
+            .map(|t| t.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// `depends_on` of every named template
+    pub fn template_dependencies(&self) -> BTreeMap<String, Vec<String>> {
+        self.templates
+            .iter()
+            .flatten()
+            .map(|(name, t)| (name.clone(), t.depends_on.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Configuration of one named template with the top-level defaults applied
+    ///
+    /// Resources and commands are inherited from the top-level `[e2b]` section;
+    /// `alias` and `template_id` are not, since they identify a single template.
+    /// A template that sets its own image source replaces the top-level `[docker]`.
+    pub fn resolve_template(&self, name: &str) -> Result<E2bConfigToml> {
+        let template = self
+            .templates
+            .as_ref()
+            .and_then(|t| t.get(name))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown template `{}`; aws_e2b.toml defines: {}",
+                    name,
+                    describe_names(&self.template_names())
+                )
+            })?;
+        let e2b = match (template.e2b.clone(), self.e2b.as_ref()) {
+            (Some(t), Some(base)) => Some(E2bSection {
+                memory_mb: t.memory_mb.or(base.memory_mb),
+                cpu_count: t.cpu_count.or(base.cpu_count),
+                start_cmd: t.start_cmd.or_else(|| base.start_cmd.clone()),
+                ready_cmd: t.ready_cmd.or_else(|| base.ready_cmd.clone()),
+                alias: t.alias,
+                template_id: t.template_id,
+            }),
+            (Some(t), None) => Some(t),
+            (None, base) => base.map(|b| E2bSection {
+                alias: None,
+                template_id: None,
+                ..b.clone()
+            }),
+        };
+        let has_source = |d: &DockerSection| {
+            d.dockerfile.is_some() || d.ecr_image.is_some() || d.docker_image.is_some()
+        };
+        let docker = match &template.docker {
+            Some(d) if has_source(d) => Some(d.clone()),
+            _ => self.docker.clone(),
+        };
+        // Tags are inherited on their own, whichever section supplies the image source
+        let tags = template
+            .docker
+            .as_ref()
+            .and_then(|d| d.tags.clone())
+            .or_else(|| self.docker.as_ref().and_then(|d| d.tags.clone()));
+        let docker = match (docker, tags) {
+            (Some(d), tags) => Some(DockerSection { tags, ..d }),
+            (None, Some(tags)) => Some(DockerSection {
+                tags: Some(tags),
+                ..Default::default()
+            }),
+            (None, None) => None,
+        };
+        Ok(E2bConfigToml {
+            e2b,
+            docker,
+            templates: None,
+        })
+    }
+
+    /// Configuration to build when no template name is given
+    ///
+    /// Files with `[templates.<name>]` tables require a name, so this fails for them.
+    pub fn select_template(&self, name: Option<&str>) -> Result<E2bConfigToml> {
+        match name {
+            Some(name) => self.resolve_template(name),
+            None if self.template_names().is_empty() => Ok(self.clone()),
+            None => Err(anyhow!(
+                "aws_e2b.toml defines [templates.<name>] tables ({}); pass a template name or --all",
+                describe_names(&self.template_names())
+            )),
+        }
+    }
+
+    /// Expand `${VAR}` and `${VAR:-default}` in the string values of `[e2b]` and `[docker]`
+    pub fn interpolate_env(&mut self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+        let mut sections = vec![(self.e2b.as_mut(), self.docker.as_mut())];
+        for template in self.templates.iter_mut().flat_map(|t| t.values_mut()) {
+            sections.push((template.e2b.as_mut(), template.docker.as_mut()));
+        }
+        let mut fields: Vec<&mut Option<String>> = Vec::new();
+        let mut tags: Vec<&mut String> = Vec::new();
+        for (e2b, docker) in sections {
+            if let Some(e2b) = e2b {
+                fields.extend([
+                    &mut e2b.start_cmd,
+                    &mut e2b.ready_cmd,
+                    &mut e2b.alias,
+                    &mut e2b.template_id,
+                ]);
+            }
+            if let Some(docker) = docker {
+                fields.extend([
+                    &mut docker.dockerfile,
+                    &mut docker.ecr_image,
+                    &mut docker.docker_image,
+                ]);
+                tags.extend(docker.tags.iter_mut().flatten());
+            }
+        }
+        let mut missing = Vec::new();
+        for value in fields.into_iter().flatten().chain(tags) {
+            *value = interpolate(value, lookup, &mut missing);
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        missing.dedup();
+        Err(anyhow!(
+            "unset environment variables without a default: {}",
+            missing.join(", ")
+        ))
+    }
+
+    /// Check settings that can be validated without knowing the target cluster
+    pub fn validate(&self) -> Result<()> {
+        if let Some(docker) = &self.docker {
+            let sources: Vec<&str> = [
+                ("dockerfile", docker.dockerfile.is_some()),
+                ("ecr-image", docker.ecr_image.is_some()),
+                ("dockerimage", docker.docker_image.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(key, set)| set.then_some(key))
+            .collect();
+            if sources.len() > 1 {
+                return Err(anyhow!(
+                    "[docker] keys {} are mutually exclusive; set only one image source",
+                    sources.join(", ")
+                ));
+            }
+        }
+        if let Some(e2b) = &self.e2b {
+            validate_resources(e2b.memory_mb, e2b.cpu_count, &ResourceLimits::default())?;
+        }
+        for name in self.template_names() {
+            self.resolve_template(&name)?
+                .validate()
+                .with_context(|| format!("template `{}`", name))?;
+        }
+        validate_dependencies(&self.template_dependencies())
+    }
+}
+
+/// Check that `depends_on` only names defined templates and has no cycles
+fn validate_dependencies(deps: &BTreeMap<String, Vec<String>>) -> Result<()> {
+    for (name, parents) in deps {
+        if let Some(unknown) = parents.iter().find(|p| !deps.contains_key(*p)) {
+            return Err(anyhow!(
+                "template `{}` depends on unknown template `{}`",
+                name,
+                unknown
+            ));
+        }
+    }
+    // Repeatedly remove templates whose dependencies are all removed; leftovers form a cycle
+    let mut remaining: Vec<&String> = deps.keys().collect();
+    while !remaining.is_empty() {
+        let before = remaining.len();
+        let blocked: Vec<&String> = remaining
+            .iter()
+            .copied()
+            .filter(|name| deps[*name].iter().any(|p| remaining.contains(&p)))
+            .collect();
+        remaining = blocked;
+        if remaining.len() == before {
+            let names: Vec<&str> = remaining.iter().map(|n| n.as_str()).collect();
+            return Err(anyhow!(
+                "templates {} have circular depends_on",
+                names.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn describe_names(names: &[String]) -> String {
+    if names.is_empty() {
+        "no templates".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Upper bounds for template resources configured for the target cluster
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceLimits {
+    pub max_cpu_count: Option<u32>,
+    pub max_memory_mb: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// Limits configured in the `[e2b]` section of the user configuration
+    pub fn from_user_config(user_cfg: Option<&UserConfig>) -> Self {
+        let e2b = user_cfg.and_then(|c| c.e2b.as_ref());
+        Self {
+            max_cpu_count: e2b.and_then(|e| e.max_cpu_count),
+            max_memory_mb: e2b.and_then(|e| e.max_memory_mb),
+        }
+    }
+}
+
+/// Smallest memory size e2b accepts for a template
+const MIN_MEMORY_MB: u32 = 128;
+
+/// Check CPU and memory against e2b requirements and the cluster limits
+pub fn validate_resources(
+    memory_mb: Option<u32>,
+    cpu_count: Option<u32>,
+    limits: &ResourceLimits,
+) -> Result<()> {
+    if let Some(cpu) = cpu_count {
+        if cpu == 0 {
+            return Err(anyhow!("cpu_count must be at least 1"));
+        }
+        if let Some(max) = limits.max_cpu_count.filter(|max| cpu > *max) {
+            return Err(anyhow!(
+                "cpu_count {} exceeds the cluster limit of {}",
+                cpu,
+                max
+            ));
+        }
+    }
+    if let Some(memory) = memory_mb {
+        if memory < MIN_MEMORY_MB {
+            return Err(anyhow!(
+                "memory_mb {} is below the minimum of {}",
+                memory,
+                MIN_MEMORY_MB
+            ));
+        }
+        if memory % 2 != 0 {
+            return Err(anyhow!("memory_mb {} must be an even number", memory));
+        }
+        if let Some(max) = limits.max_memory_mb.filter(|max| memory > *max) {
+            return Err(anyhow!(
+                "memory_mb {} exceeds the cluster limit of {}",
+                memory,
+                max
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` references; `$${` produces a literal `${`.
+/// Names of unset variables without a default are pushed to `missing`.
+fn interpolate(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    missing: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start]);
+            out.push('{');
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let expr = &rest[start + 2..start + 2 + len];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        // Like the shell, `:-` also applies the default when the variable is empty
+        match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => missing.push(name.to_string()),
+        }
+        rest = &rest[start + 3 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Deserialize TOML, adding a "did you mean" hint to unknown-key errors
+fn parse_toml<T: serde::de::DeserializeOwned>(raw: &str) -> Result<T> {
+    toml::from_str(raw).map_err(|err| {
+        let message = err.to_string();
+        match suggest_known_field(&message) {
+            Some(suggestion) => anyhow!("{}help: did you mean `{}`?", message, suggestion),
+            None => anyhow!(message),
+        }
+    })
+}
+
+/// Find the closest expected key for an `unknown field` error from serde
+fn suggest_known_field(message: &str) -> Option<String> {
+    let rest = message.split("unknown field `").nth(1)?;
+    let (unknown, rest) = rest.split_once('`')?;
+    let expected = rest.split("expected").nth(1)?;
+    let normalize = |s: &str| s.to_ascii_lowercase().replace('-', "_");
+    let unknown_norm = normalize(unknown);
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| {
+            (
+                candidate,
+                edit_distance(&unknown_norm, &normalize(candidate)),
+            )
+        })
+        .filter(|(candidate, distance)| *distance <= 2.max(candidate.len() / 3))
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate.to_string())
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            current[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+/// Path of the user configuration
+///
+/// `--user-config` and `AWS_E2B_CONFIG` take precedence. Otherwise an existing
+/// `$XDG_CONFIG_HOME/aws_e2b/config.toml` is used, then `~/.aws_e2b/config.toml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    explicit_user_config_path()
+        .or_else(|| default_user_config_path(&|name| env::var(name).ok(), &|p| p.exists()))
+}
+
+/// Path of the user configuration, or an error when no location can be determined
+pub fn require_user_config_path() -> Result<PathBuf> {
+    user_config_path().ok_or_else(|| {
+        anyhow!(
+            "Cannot locate the user configuration: set HOME, XDG_CONFIG_HOME or {}, or pass --user-config",
+            USER_CONFIG_ENV
+        )
+    })
+}
+
+/// Use the file given by `--user-config`; call once before reading the user configuration
+pub fn set_user_config_override(path: PathBuf) {
+    let _ = USER_CONFIG_OVERRIDE.set(path);
+}
+
+/// User configuration path given with `--user-config` or `AWS_E2B_CONFIG`
+fn explicit_user_config_path() -> Option<PathBuf> {
+    USER_CONFIG_OVERRIDE.get().cloned().or_else(|| {
+        env::var_os(USER_CONFIG_ENV)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+fn default_user_config_path(
+    lookup: &dyn Fn(&str) -> Option<String>,
+    exists: &dyn Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    let non_empty = |name: &str| lookup(name).filter(|v| !v.is_empty());
+    let xdg =
+        non_empty("XDG_CONFIG_HOME").map(|dir| Path::new(&dir).join("aws_e2b").join("config.toml"));
+    let home = non_empty("HOME").map(|home| Path::new(&home).join(".aws_e2b").join("config.toml"));
+    match (xdg, home) {
+        (Some(xdg), _) if exists(&xdg) => Some(xdg),
+        (xdg, home) => home.or(xdg),
+    }
+}
+
+/// Select the profile given by `--profile`; call once before reading the user configuration
+pub fn set_profile_override(profile: String) {
+    let _ = PROFILE_OVERRIDE.set(profile);
+}
+
+/// Refuse to read a user configuration with insecure permissions instead of warning
+pub fn set_strict_permissions() {
+    let _ = STRICT_PERMISSIONS.set(true);
+}
+
+/// Read user configuration `~/.aws_e2b/config.toml` with the active profile applied
 pub fn read_user_config() -> Result<Option<UserConfig>> {
-    let home = env::var("HOME").unwrap_or_default();
-    if home.is_empty() {
+    let Some(cfg) = read_raw_user_config()? else {
         return Ok(None);
-    }
-    let path = Path::new(&home).join(".aws_e2b").join("config.toml");
+    };
+    apply_profile(cfg, active_profile_name().as_deref()).map(Some)
+}
+
+/// Read `~/.aws_e2b/config.toml` with the named profile applied, ignoring `--profile`
+pub fn read_profile_config(profile: &str) -> Result<UserConfig> {
+    let cfg = read_raw_user_config()?.ok_or_else(|| {
+        anyhow!(
+            "Profile `{}` needs a [profiles.{}] table, but there is no user configuration",
+            profile,
+            profile
+        )
+    })?;
+    apply_profile(cfg, Some(profile))
+}
+
+/// Read `~/.aws_e2b/config.toml` without applying any profile
+pub fn read_raw_user_config() -> Result<Option<UserConfig>> {
+    let Some(path) = user_config_path() else {
+        return Ok(None);
+    };
     if !path.exists() {
+        if explicit_user_config_path().is_some() {
+            return Err(anyhow!(
+                "User configuration {} does not exist (set by --user-config or {})",
+                path.display(),
+                USER_CONFIG_ENV
+            ));
+        }
         return Ok(None);
     }
+    check_user_config_permissions(&path)?;
     let raw = fs::read_to_string(&path)
         .with_context(|| format!("failed to read user configuration: {}", path.display()))?;
-    let cfg: UserConfig = toml::from_str(&raw)
+    let cfg: UserConfig = parse_toml(&raw)
         .with_context(|| format!("failed to parse user configuration: {}", path.display()))?;
     Ok(Some(cfg))
 }
+
+/// Warn about (or, with `--strict`, reject) a user configuration others can read
+fn check_user_config_permissions(path: &Path) -> Result<()> {
+    let problems = permission_problems(path)?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "{} {}; run `aws_e2b config fix-permissions` to restrict it to the current user",
+        path.display(),
+        problems.join(" and ")
+    );
+    if STRICT_PERMISSIONS.get().copied().unwrap_or(false) {
+        return Err(anyhow!(message));
+    }
+    PERMISSION_WARNING.call_once(|| warn!("{}", message));
+    Ok(())
+}
+
+/// Reasons why `path` may expose credentials to other users
+#[cfg(unix)]
+pub fn permission_problems(path: &Path) -> Result<Vec<String>> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+    let mut problems = Vec::new();
+    let mode = meta.mode() & 0o777;
+    if mode & 0o077 != 0 {
+        problems.push(format!(
+            "is accessible by group or other users (mode {:o})",
+            mode
+        ));
+    }
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let euid = unsafe { libc::geteuid() };
+    if meta.uid() != euid {
+        problems.push(format!("is owned by another user (uid {})", meta.uid()));
+    }
+    Ok(problems)
+}
+
+#[cfg(not(unix))]
+pub fn permission_problems(_path: &Path) -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// Restrict `~/.aws_e2b/config.toml` to mode 0600 and its directory to 0700
+pub fn fix_user_config_permissions() -> Result<PathBuf> {
+    let path = require_user_config_path()?;
+    if !path.exists() {
+        return Err(anyhow!("{} does not exist", path.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to set permissions: {}", path.display()))?;
+        // Only tighten the directory this tool owns, not an arbitrary custom location
+        if let Some(dir) = path
+            .parent()
+            .filter(|_| explicit_user_config_path().is_none())
+        {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("failed to set permissions: {}", dir.display()))?;
+        }
+    }
+    let remaining = permission_problems(&path)?;
+    if !remaining.is_empty() {
+        return Err(anyhow!(
+            "{} {}; change its owner with chown",
+            path.display(),
+            remaining.join(" and ")
+        ));
+    }
+    Ok(path)
+}
+
+/// Profile passed with the global `--profile` flag
+pub fn profile_override() -> Option<&'static str> {
+    PROFILE_OVERRIDE.get().map(String::as_str)
+}
+
+/// Profile requested on the command line or through `AWS_E2B_PROFILE`
+pub fn active_profile_name() -> Option<String> {
+    PROFILE_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()))
+}
+
+/// Overlay the selected profile (or `default_profile`) on the top-level sections
+fn apply_profile(mut cfg: UserConfig, requested: Option<&str>) -> Result<UserConfig> {
+    let Some(name) = requested
+        .map(str::to_string)
+        .or_else(|| cfg.default_profile.clone())
+    else {
+        return Ok(cfg);
+    };
+    let profile = cfg
+        .profiles
+        .as_ref()
+        .and_then(|p| p.get(&name))
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown profile `{}`: add a [profiles.{}] table to ~/.aws_e2b/config.toml",
+                name,
+                name
+            )
+        })?;
+    cfg.aws = merge_section(profile.aws, cfg.aws.take(), |p, b| UserAwsSection {
+        aws_region: p.aws_region.or(b.aws_region),
+        ecr_token_cache: p.ecr_token_cache.or(b.ecr_token_cache),
+        pull_through_cache: p.pull_through_cache.or(b.pull_through_cache),
+        replication: p.replication.or(b.replication),
+    });
+    cfg.e2b = merge_section(profile.e2b, cfg.e2b.take(), |p, b| UserE2bSection {
+        e2b_domain: p.e2b_domain.or(b.e2b_domain),
+        e2b_access_token: p.e2b_access_token.or(b.e2b_access_token),
+        e2b_api_key: p.e2b_api_key.or(b.e2b_api_key),
+        e2b_team_id: p.e2b_team_id.or(b.e2b_team_id),
+        max_cpu_count: p.max_cpu_count.or(b.max_cpu_count),
+        max_memory_mb: p.max_memory_mb.or(b.max_memory_mb),
+    });
+    if profile.replicas.is_some() {
+        cfg.replicas = profile.replicas;
+    }
+    cfg.default_profile = Some(name);
+    Ok(cfg)
+}
+
+/// Combine a profile section with the top-level one, preferring profile values
+fn merge_section<T>(
+    profile: Option<T>,
+    base: Option<T>,
+    merge: impl FnOnce(T, T) -> T,
+) -> Option<T> {
+    match (profile, base) {
+        (Some(p), Some(b)) => Some(merge(p, b)),
+        (p, b) => p.or(b),
+    }
+}
+
+/// Load `~/.aws_e2b/config.toml` for editing, preserving comments and layout
+pub fn read_user_config_document() -> Result<DocumentMut> {
+    let path = require_user_config_path()?;
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read user configuration: {}", path.display()))?;
+    raw.parse()
+        .with_context(|| format!("failed to parse user configuration: {}", path.display()))
+}
+
+/// Write `~/.aws_e2b/config.toml`, readable only by the current user
+pub fn write_user_config_document(doc: &DocumentMut) -> Result<PathBuf> {
+    let path = require_user_config_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory: {}", dir.display()))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("failed to write user configuration: {}", path.display()))?;
+    // The mode above only applies to new files; tighten existing ones as well
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to set permissions: {}", path.display()))?;
+    }
+    std::io::Write::write_all(&mut file, doc.to_string().as_bytes())
+        .with_context(|| format!("failed to write user configuration: {}", path.display()))?;
+    Ok(path)
+}
+
+/// Get the `[section]` table, or `[profiles.<profile>.section]` when a profile is given,
+/// creating it if needed
+pub fn user_section_mut<'a>(
+    doc: &'a mut DocumentMut,
+    profile: Option<&str>,
+    section: &str,
+) -> Result<&'a mut Table> {
+    let mut table = doc.as_table_mut();
+    let path: Vec<&str> = match profile {
+        Some(name) => vec!["profiles", name, section],
+        None => vec![section],
+    };
+    for (i, key) in path.iter().enumerate() {
+        let is_leaf = i + 1 == path.len();
+        let item = table.entry(key).or_insert_with(|| {
+            let mut t = Table::new();
+            t.set_implicit(!is_leaf);
+            toml_edit::Item::Table(t)
+        });
+        table = item
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`{}` in ~/.aws_e2b/config.toml is not a table", key))?;
+    }
+    Ok(table)
+}
+
+/// Resolve the AWS region: environment variable `AWS_REGION` > user configuration
+pub fn resolve_aws_region(user_cfg: Option<&UserConfig>) -> Result<String> {
+    let user_aws_region = user_cfg.and_then(|c| c.aws.as_ref().and_then(|a| a.aws_region.clone()));
+    env::var("AWS_REGION").ok().or(user_aws_region).ok_or_else(|| {
+        anyhow!(
+            "Missing AWS region: set AWS_REGION or configure [aws].aws_region in ~/.aws_e2b/config.toml"
+        )
+    })
+}
+
+/// Where an effective setting came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Flag(&'static str),
+    Env(&'static str),
+    File { path: PathBuf, key: String },
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::File { path, key } => write!(f, "{} {}", path.display(), key),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// A setting together with the place its value was taken from
+#[derive(Debug, Clone)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Sourced<T> {
+    /// A built-in default value
+    pub fn default_value(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+}
+
+/// Return the first candidate that has a value
+pub fn pick<T, const N: usize>(candidates: [(Option<T>, Source); N]) -> Option<Sourced<T>> {
+    candidates
+        .into_iter()
+        .find_map(|(value, source)| value.map(|value| Sourced { value, source }))
+}
+
+/// Cluster settings: environment > active profile > top-level user configuration
+///
+/// `template build`, the alias commands and forwarded `e2b` commands take their
+/// region, domain and token from here and `config show` prints it, so they all
+/// apply the same precedence.
+#[derive(Debug, Default)]
+pub struct ClusterSettings {
+    pub profile: Option<Sourced<String>>,
+    pub aws_region: Option<Sourced<String>>,
+    pub e2b_domain: Option<Sourced<String>>,
+    pub e2b_team_id: Option<Sourced<String>>,
+    pub e2b_access_token: Option<Sourced<String>>,
+    pub e2b_api_key: Option<Sourced<String>>,
+}
+
+impl ClusterSettings {
+    /// Resolve the settings from `~/.aws_e2b/config.toml` and the environment
+    pub fn read() -> Result<Self> {
+        let raw = read_raw_user_config()?;
+        let path = user_config_path().unwrap_or_default();
+        Ok(Self::resolve(raw.as_ref(), &path, &|var| {
+            env::var(var).ok()
+        }))
+    }
+
+    fn resolve(
+        raw: Option<&UserConfig>,
+        path: &Path,
+        lookup: &dyn Fn(&str) -> Option<String>,
+    ) -> Self {
+        let file = |key: String| Source::File {
+            path: path.to_path_buf(),
+            key,
+        };
+        let profile = pick([
+            (
+                profile_override().map(str::to_string),
+                Source::Flag("--profile"),
+            ),
+            (
+                lookup(PROFILE_ENV).filter(|p| !p.is_empty()),
+                Source::Env(PROFILE_ENV),
+            ),
+            (
+                raw.and_then(|c| c.default_profile.clone()),
+                file("default_profile".to_string()),
+            ),
+        ]);
+        let profile_cfg = profile.as_ref().and_then(|p| {
+            raw.and_then(|c| c.profiles.as_ref())
+                .and_then(|profiles| profiles.get(&p.value))
+        });
+        let top = raw.map(|c| ProfileSection {
+            aws: c.aws.clone(),
+            e2b: c.e2b.clone(),
+            replicas: None,
+        });
+        // An environment variable, then the active profile, then the top-level section
+        let setting = |var: Option<&'static str>,
+                       section: &str,
+                       key: &str,
+                       get: &dyn Fn(&ProfileSection) -> Option<String>| {
+            let profile_key = profile
+                .as_ref()
+                .map(|p| format!("[profiles.{}][{}].{}", p.value, section, key))
+                .unwrap_or_default();
+            pick([
+                (var.and_then(lookup), Source::Env(var.unwrap_or_default())),
+                (profile_cfg.and_then(get), file(profile_key)),
+                (
+                    top.as_ref().and_then(get),
+                    file(format!("[{}].{}", section, key)),
+                ),
+            ])
+        };
+        Self {
+            aws_region: setting(Some("AWS_REGION"), "aws", "aws_region", &|p| {
+                p.aws.as_ref()?.aws_region.clone()
+            }),
+            e2b_domain: setting(Some("E2B_DOMAIN"), "e2b", "e2b_domain", &|p| {
+                p.e2b.as_ref()?.e2b_domain.clone()
+            }),
+            e2b_team_id: setting(None, "e2b", "e2b_team_id", &|p| {
+                p.e2b.as_ref()?.e2b_team_id.clone()
+            }),
+            e2b_access_token: setting(Some("E2B_ACCESS_TOKEN"), "e2b", "e2b_access_token", &|p| {
+                p.e2b.as_ref()?.e2b_access_token.clone()
+            }),
+            e2b_api_key: setting(Some("E2B_API_KEY"), "e2b", "e2b_api_key", &|p| {
+                p.e2b.as_ref()?.e2b_api_key.clone()
+            }),
+            profile,
+        }
+    }
+
+    pub fn require_aws_region(&self) -> Result<String> {
+        required(&self.aws_region, "Missing AWS region: set AWS_REGION or configure [aws].aws_region in ~/.aws_e2b/config.toml")
+    }
+
+    pub fn require_e2b_domain(&self) -> Result<String> {
+        required(&self.e2b_domain, "Missing e2b domain: set E2B_DOMAIN or configure [e2b].e2b_domain in ~/.aws_e2b/config.toml")
+    }
+
+    pub fn require_e2b_access_token(&self) -> Result<String> {
+        required(&self.e2b_access_token, "Missing e2b access token: set E2B_ACCESS_TOKEN or configure [e2b].e2b_access_token in ~/.aws_e2b/config.toml")
+    }
+}
+
+fn required(setting: &Option<Sourced<String>>, message: &str) -> Result<String> {
+    setting
+        .as_ref()
+        .map(|s| s.value.clone())
+        .ok_or_else(|| anyhow!("{}", message))
+}
+
+/// Directory for cached data such as ECR tokens, `~/.aws_e2b/cache`
+/// or `$XDG_CACHE_HOME/aws_e2b` when `HOME` is unset
+pub fn user_cache_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+    non_empty("HOME")
+        .map(|home| Path::new(&home).join(".aws_e2b").join("cache"))
+        .or_else(|| non_empty("XDG_CACHE_HOME").map(|dir| Path::new(&dir).join("aws_e2b")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+        default_profile = "staging"
+
+        [aws]
+        aws_region = "us-east-1"
+
+        [e2b]
+        e2b_domain = "dev.example.com"
+        e2b_team_id = "team-dev"
+
+        [profiles.staging.e2b]
+        e2b_domain = "staging.example.com"
+
+        [profiles.prod.aws]
+        aws_region = "eu-west-1"
+    "#;
+
+    #[test]
+    fn apply_profile_overlays_profile_values() {
+        let cfg: UserConfig = toml::from_str(PROFILES).unwrap();
+        let cfg = apply_profile(cfg, Some("prod")).unwrap();
+        assert_eq!(cfg.aws.unwrap().aws_region.as_deref(), Some("eu-west-1"));
+        let e2b = cfg.e2b.unwrap();
+        assert_eq!(e2b.e2b_domain.as_deref(), Some("dev.example.com"));
+        assert_eq!(e2b.e2b_team_id.as_deref(), Some("team-dev"));
+    }
+
+    #[test]
+    fn apply_profile_uses_default_profile() {
+        let cfg: UserConfig = toml::from_str(PROFILES).unwrap();
+        let cfg = apply_profile(cfg, None).unwrap();
+        assert_eq!(
+            cfg.e2b.unwrap().e2b_domain.as_deref(),
+            Some("staging.example.com")
+        );
+    }
+
+    #[test]
+    fn cluster_settings_prefer_env_then_profile_then_top_level() {
+        let cfg: UserConfig = toml::from_str(PROFILES).unwrap();
+        let path = Path::new("config.toml");
+        let env = |var: &str| (var == "AWS_REGION").then(|| "ap-south-1".to_string());
+        let settings = ClusterSettings::resolve(Some(&cfg), path, &env);
+        let region = settings.aws_region.unwrap();
+        assert_eq!(region.value, "ap-south-1");
+        assert_eq!(region.source, Source::Env("AWS_REGION"));
+        let domain = settings.e2b_domain.unwrap();
+        assert_eq!(domain.value, "staging.example.com");
+        assert_eq!(
+            domain.source.to_string(),
+            "config.toml [profiles.staging][e2b].e2b_domain"
+        );
+        let team = settings.e2b_team_id.unwrap();
+        assert_eq!(team.source.to_string(), "config.toml [e2b].e2b_team_id");
+        assert!(settings.e2b_access_token.is_none());
+    }
+
+    #[test]
+    fn pick_prefers_first_present_candidate() {
+        let resolved = pick([
+            (None, Source::Flag("--alias")),
+            (Some("from-file".to_string()), Source::Default),
+        ]);
+        assert_eq!(resolved.map(|s| s.value).as_deref(), Some("from-file"));
+    }
+
+    #[test]
+    fn parse_toml_suggests_known_keys() {
+        let err = parse_toml::<E2bConfigToml>("[e2b]\nmemory_MB = 2048\n").unwrap_err();
+        assert!(err.to_string().contains("did you mean `memory_mb`?"));
+        let err = parse_toml::<E2bConfigToml>("[e2b]\nstart-cmd = \"x\"\n").unwrap_err();
+        assert!(err.to_string().contains("did you mean `start_cmd`?"));
+        let cfg = parse_toml::<E2bConfigToml>("[docker]\nbase-image = \"node:20\"\n").unwrap();
+        assert_eq!(
+            cfg.docker.and_then(|d| d.docker_image).as_deref(),
+            Some("node:20")
+        );
+    }
+
+    #[test]
+    fn validate_rejects_conflicting_sources_and_bad_resources() {
+        let cfg: E2bConfigToml =
+            parse_toml("[docker]\ndockerfile = \"a\"\necr-image = \"b\"\n").unwrap();
+        assert!(cfg.validate().is_err());
+        let limits = ResourceLimits {
+            max_cpu_count: Some(8),
+            max_memory_mb: None,
+        };
+        assert!(validate_resources(Some(4096), Some(8), &limits).is_ok());
+        assert!(validate_resources(None, Some(16), &limits).is_err());
+        assert!(validate_resources(Some(1025), None, &limits).is_err());
+        assert!(validate_resources(Some(64), None, &limits).is_err());
+    }
+
+    #[test]
+    fn interpolate_expands_variables_and_defaults() {
+        let lookup = |name: &str| (name == "TAG").then(|| "v2".to_string());
+        let mut missing = Vec::new();
+        assert_eq!(
+            interpolate("img:${TAG}-${SUFFIX:-dev}", &lookup, &mut missing),
+            "img:v2-dev"
+        );
+        assert_eq!(
+            interpolate("echo $${HOME}", &lookup, &mut missing),
+            "echo ${HOME}"
+        );
+        assert!(missing.is_empty());
+        interpolate("${ACCOUNT}.dkr.ecr", &lookup, &mut missing);
+        assert_eq!(missing, vec!["ACCOUNT".to_string()]);
+    }
+
+    #[test]
+    fn apply_profile_rejects_unknown_profile() {
+        let cfg: UserConfig = toml::from_str(PROFILES).unwrap();
+        assert!(apply_profile(cfg, Some("missing")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_problems_flags_shared_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("aws_e2b_perms_{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(permission_problems(&path).unwrap().len(), 1);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(permission_problems(&path).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn default_user_config_path_prefers_existing_xdg_file() {
+        let vars = |xdg: bool, home: bool| {
+            move |name: &str| match name {
+                "XDG_CONFIG_HOME" if xdg => Some("/xdg".to_string()),
+                "HOME" if home => Some("/home/u".to_string()),
+                _ => None,
+            }
+        };
+        let legacy = PathBuf::from("/home/u/.aws_e2b/config.toml");
+        let xdg = PathBuf::from("/xdg/aws_e2b/config.toml");
+        assert_eq!(
+            default_user_config_path(&vars(true, true), &|_| false),
+            Some(legacy.clone())
+        );
+        assert_eq!(
+            default_user_config_path(&vars(true, true), &|p| p == xdg.as_path()),
+            Some(xdg.clone())
+        );
+        assert_eq!(
+            default_user_config_path(&vars(true, false), &|_| false),
+            Some(xdg)
+        );
+        assert_eq!(
+            default_user_config_path(&vars(false, false), &|_| false),
+            None
+        );
+    }
+
+    #[test]
+    fn find_e2b_toml_searches_up_to_git_root() {
+        let root = env::temp_dir().join(format!("aws_e2b_find_{}", std::process::id()));
+        let repo = root.join("repo");
+        let nested = repo.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::write(root.join("aws_e2b.toml"), "").unwrap();
+        assert_eq!(find_e2b_toml(&nested), None);
+        fs::write(repo.join("a").join("aws_e2b.toml"), "").unwrap();
+        assert_eq!(
+            find_e2b_toml(&nested),
+            Some(repo.join("a").join("aws_e2b.toml"))
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn find_e2b_toml_outside_a_repository_only_checks_start() {
+        let root = env::temp_dir().join(format!("aws_e2b_find_norepo_{}", std::process::id()));
+        let nested = root.join("a");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join("aws_e2b.toml"), "").unwrap();
+        let found = find_e2b_toml(&nested);
+        fs::write(nested.join("aws_e2b.toml"), "").unwrap();
+        let found_in_start = find_e2b_toml(&nested);
+        fs::remove_dir_all(root).unwrap();
+        assert_eq!(found, None);
+        assert_eq!(found_in_start, Some(nested.join("aws_e2b.toml")));
+    }
+
+    #[test]
+    fn resolve_template_inherits_shared_defaults() {
+        let cfg: E2bConfigToml = parse_toml(
+            r#"
+            [e2b]
+            memory_mb = 2048
+            start_cmd = "/start.sh"
+            alias = "shared"
+
+            [docker]
+            dockerfile = "./Dockerfile"
+            tags = ["latest"]
+
+            [templates.python.e2b]
+            alias = "python"
+            cpu_count = 2
+
+            [templates.python.docker]
+            tags = ["python-{date}"]
+
+            [templates.node.docker]
+            dockerimage = "node:20"
+            "#,
+        )
+        .unwrap();
+        cfg.validate().unwrap();
+        assert_eq!(cfg.template_names(), vec!["node", "python"]);
+
+        let python = cfg.resolve_template("python").unwrap();
+        let e2b = python.e2b.unwrap();
+        assert_eq!(e2b.memory_mb, Some(2048));
+        assert_eq!(e2b.cpu_count, Some(2));
+        assert_eq!(e2b.start_cmd.as_deref(), Some("/start.sh"));
+        assert_eq!(e2b.alias.as_deref(), Some("python"));
+        let docker = python.docker.unwrap();
+        assert_eq!(docker.dockerfile.as_deref(), Some("./Dockerfile"));
+        assert_eq!(docker.tags, Some(vec!["python-{date}".to_string()]));
+
+        let node = cfg.resolve_template("node").unwrap();
+        assert_eq!(node.e2b.unwrap().alias, None);
+        let docker = node.docker.unwrap();
+        assert_eq!(docker.dockerfile, None);
+        assert_eq!(docker.docker_image.as_deref(), Some("node:20"));
+        assert_eq!(docker.tags, Some(vec!["latest".to_string()]));
+
+        assert!(cfg.resolve_template("rust").is_err());
+        assert!(cfg.select_template(None).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_unknown_and_circular_dependencies() {
+        let parse = |raw: &str| parse_toml::<E2bConfigToml>(raw).unwrap().validate();
+        parse(
+            r#"
+            [templates.base]
+            [templates.child]
+            depends_on = ["base"]
+            "#,
+        )
+        .unwrap();
+        let err = parse("[templates.child]\ndepends_on = [\"base\"]").unwrap_err();
+        assert!(err.to_string().contains("unknown template `base`"));
+        let err = parse(
+            r#"
+            [templates.a]
+            depends_on = ["b"]
+            [templates.b]
+            depends_on = ["a"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("circular"));
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use toml_edit::value;
+
+use crate::args::{BuildArgs, ConfigCommand, UseContextArgs, ValidateArgs};
+use crate::build::TemplateSettings;
+use crate::config::{
+    fix_user_config_permissions, load_e2b_toml, read_raw_user_config, read_user_config,
+    read_user_config_document, require_user_config_path, validate_resources,
+    write_user_config_document, ClusterSettings, ResourceLimits, Source, Sourced,
+};
+use crate::init::run_config_init;
+use crate::secrets::is_secret_reference;
+
+/// Handle the `config` subcommands
+pub async fn run_config_command(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Init(args) => run_config_init(args).await,
+        ConfigCommand::UseContext(args) => run_use_context(args),
+        ConfigCommand::Show(args) => run_show(args),
+        ConfigCommand::Validate(args) => run_validate(args),
+        ConfigCommand::FixPermissions => {
+            let path = fix_user_config_permissions()?;
+            info!("Restricted {} to the current user", path.display());
+            Ok(())
+        }
+    }
+}
+
+/// Persist `default_profile` in the user configuration after checking the profile exists
+fn run_use_context(args: UseContextArgs) -> Result<()> {
+    let cfg = read_raw_user_config()?.ok_or_else(|| {
+        anyhow!(
+            "No user configuration found at {}",
+            require_user_config_path()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| "~/.aws_e2b/config.toml".to_string())
+        )
+    })?;
+    let known = cfg.profiles.unwrap_or_default();
+    if !known.contains_key(&args.name) {
+        let names = known.keys().cloned().collect::<Vec<_>>().join(", ");
+        return Err(anyhow!(
+            "Unknown profile `{}`; available profiles: {}",
+            args.name,
+            if names.is_empty() { "none" } else { &names }
+        ));
+    }
+
+    let mut doc = read_user_config_document()?;
+    doc["default_profile"] = value(args.name.as_str());
+    write_user_config_document(&doc)?;
+    info!("Default profile set to {}", args.name);
+    Ok(())
+}
+
+/// Check both configuration files and report every problem found
+fn run_validate(args: ValidateArgs) -> Result<()> {
+    let mut problems = Vec::new();
+
+    let user_cfg = match read_user_config() {
+        Ok(cfg) => {
+            if cfg.is_some() {
+                info!("User configuration is valid");
+            }
+            cfg
+        }
+        Err(err) => {
+            problems.push(format!("{:#}", err));
+            None
+        }
+    };
+
+    match load_e2b_toml(args.config_path.as_deref(), false) {
+        Ok((cfg, Some(path))) => {
+            let limits = ResourceLimits::from_user_config(user_cfg.as_ref());
+            let mut targets = vec![(None, cfg.e2b.clone().unwrap_or_default())];
+            for name in cfg.template_names() {
+                if let Ok(template) = cfg.resolve_template(&name) {
+                    targets.push((Some(name), template.e2b.unwrap_or_default()));
+                }
+            }
+            let before = problems.len();
+            for (name, e2b) in targets {
+                if let Err(err) = validate_resources(e2b.memory_mb, e2b.cpu_count, &limits) {
+                    match name {
+                        Some(name) => problems.push(format!(
+                            "{}: template `{}`: {:#}",
+                            path.display(),
+                            name,
+                            err
+                        )),
+                        None => problems.push(format!("{}: {:#}", path.display(), err)),
+                    }
+                }
+            }
+            if problems.len() == before {
+                info!("{} is valid", path.display());
+            }
+        }
+        Ok((_, None)) => info!("No aws_e2b.toml found in the current directory or its parents"),
+        Err(err) => problems.push(format!("{:#}", err)),
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("{}\n", problem);
+    }
+    Err(anyhow!("found {} configuration problem(s)", problems.len()))
+}
+
+/// An effective setting together with its provenance
+struct Setting {
+    name: &'static str,
+    value: Option<String>,
+    source: Option<Source>,
+    secret: bool,
+}
+
+impl Setting {
+    fn new<T: ToString>(name: &'static str, resolved: Option<Sourced<T>>) -> Self {
+        let (value, source) = match resolved {
+            Some(s) => (Some(s.value.to_string()), Some(s.source)),
+            None => (None, None),
+        };
+        Self {
+            name,
+            value,
+            source,
+            secret: false,
+        }
+    }
+
+    fn secret(mut self) -> Self {
+        self.secret = true;
+        self
+    }
+
+    fn display_value(&self) -> String {
+        match &self.value {
+            None => "-".to_string(),
+            // References name a secret store rather than containing the secret
+            Some(v) if self.secret && !is_secret_reference(v) => mask_secret(v),
+            Some(v) => v.clone(),
+        }
+    }
+
+    fn display_source(&self) -> String {
+        self.source
+            .as_ref()
+            .map_or_else(|| "not set".to_string(), Source::to_string)
+    }
+}
+
+/// Print every effective build setting with the source it was taken from
+///
+/// The values are resolved by the same code `template build` uses.
+fn run_show(args: BuildArgs) -> Result<()> {
+    if args.all {
+        return Err(anyhow!(
+            "config show describes a single template; pass its name instead of --all"
+        ));
+    }
+    let (e2b_cfg, e2b_path) = load_e2b_toml(args.config_path.as_deref(), args.no_config)?;
+    let template = TemplateSettings::resolve(
+        &args,
+        &e2b_cfg,
+        e2b_path.as_deref(),
+        args.template.as_deref(),
+    )?;
+    let cluster = ClusterSettings::read()?;
+
+    let settings = vec![
+        Setting::new("memory_mb", Some(template.memory_mb)),
+        Setting::new("cpu_count", Some(template.cpu_count)),
+        Setting::new("start_cmd", template.start_cmd),
+        Setting::new("ready_cmd", template.ready_cmd),
+        Setting::new("alias", template.alias),
+        Setting::new("template_id", template.template_id),
+        Setting::new("docker_source", Some(template.image)),
+        Setting::new("profile", cluster.profile),
+        Setting::new("aws_region", cluster.aws_region),
+        Setting::new("e2b_domain", cluster.e2b_domain),
+        Setting::new("e2b_team_id", cluster.e2b_team_id),
+        Setting::new("e2b_access_token", cluster.e2b_access_token).secret(),
+        Setting::new("e2b_api_key", cluster.e2b_api_key).secret(),
+    ];
+    print_settings(&settings);
+    Ok(())
+}
+
+fn print_settings(settings: &[Setting]) {
+    let values: Vec<String> = settings.iter().map(Setting::display_value).collect();
+    let name_width = settings.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    let value_width = values.iter().map(|v| v.len()).max().unwrap_or(0);
+    println!(
+        "{:name_width$}  {:value_width$}  SOURCE",
+        "SETTING",
+        "VALUE",
+        name_width = name_width,
+        value_width = value_width
+    );
+    for (setting, value) in settings.iter().zip(values) {
+        println!(
+            "{:name_width$}  {:value_width$}  {}",
+            setting.name,
+            value,
+            setting.display_source(),
+            name_width = name_width,
+            value_width = value_width
+        );
+    }
+}
+
+/// Hide a secret, keeping only the last four characters of long values
+fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.trim().chars().collect();
+    if chars.len() <= 8 {
+        return "********".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("********{}", tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_secret_hides_most_characters() {
+        assert_eq!(mask_secret("short"), "********");
+        assert_eq!(mask_secret("e2b_abcdefghijkl"), "********ijkl");
+    }
+}
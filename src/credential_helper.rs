@@ -0,0 +1,132 @@
+use std::io::{self, Read, Write};
+
+use anyhow::{Context, Result};
+use aws_sdk_ecr as ecr;
+use serde::Serialize;
+
+use crate::args::{CredentialHelperAction, CredentialHelperArgs};
+use crate::aws_utils::{fetch_aws_account_id, load_aws_config};
+use crate::config::{read_user_config, resolve_aws_region};
+use crate::ecr_auth::EcrAuthProvider;
+
+/// Message defined by the credential helper protocol for unknown servers
+const CREDENTIALS_NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Credentials returned to Docker by the `get` action
+#[derive(Serialize)]
+struct HelperCredentials<'a> {
+    #[serde(rename = "ServerURL")]
+    server_url: &'a str,
+    #[serde(rename = "Username")]
+    username: &'a str,
+    #[serde(rename = "Secret")]
+    secret: &'a str,
+}
+
+/// Entry point of the `credential-helper` subcommand implementing the
+/// docker-credential-helper protocol for Amazon ECR registries
+pub async fn run_credential_helper(args: CredentialHelperArgs) -> Result<()> {
+    match args.action {
+        CredentialHelperAction::Get => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .context("failed to read server URL from stdin")?;
+            let server_url = input.trim();
+            let Some((account_id, region)) = parse_ecr_registry(server_url) else {
+                // Docker recognizes this exact message and falls back to anonymous access
+                write_stdout(CREDENTIALS_NOT_FOUND)?;
+                std::process::exit(1);
+            };
+            let user_cfg = read_user_config()?;
+            let use_token_cache = user_cfg
+                .as_ref()
+                .and_then(|c| c.aws.as_ref().and_then(|a| a.ecr_token_cache))
+                .unwrap_or(false);
+            let shared_config = load_aws_config(&region).await;
+            let provider = EcrAuthProvider::new(
+                ecr::Client::new(&shared_config),
+                &account_id,
+                &region,
+                use_token_cache,
+            );
+            let auth = provider.get().await?;
+            let creds = HelperCredentials {
+                server_url,
+                username: auth.credentials.username.as_deref().unwrap_or(""),
+                secret: auth.credentials.password.as_deref().unwrap_or(""),
+            };
+            write_stdout(&serde_json::to_string(&creds)?)
+        }
+        CredentialHelperAction::List => {
+            // Report the registry of the configured account and region
+            let user_cfg = read_user_config()?;
+            let region = resolve_aws_region(user_cfg.as_ref())?;
+            let shared_config = load_aws_config(&region).await;
+            let account_id =
+                fetch_aws_account_id(&aws_sdk_sts::Client::new(&shared_config)).await?;
+            let registry = format!("https://{}.dkr.ecr.{}.amazonaws.com", account_id, region);
+            let list = serde_json::json!({ registry: "AWS" });
+            write_stdout(&list.to_string())
+        }
+        CredentialHelperAction::Store | CredentialHelperAction::Erase => {
+            // ECR tokens are short-lived and fetched on demand, so there is nothing to persist
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).ok();
+            Ok(())
+        }
+    }
+}
+
+fn write_stdout(content: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(content.as_bytes())?;
+    stdout.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Extract the account identifier and region from an ECR registry URL such as
+/// `https://123456789012.dkr.ecr.us-east-1.amazonaws.com`
+fn parse_ecr_registry(server_url: &str) -> Option<(String, String)> {
+    let host = server_url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()?;
+    let mut parts = host.split('.');
+    let account_id = parts.next()?;
+    if account_id.is_empty() || !account_id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if parts.next()? != "dkr" || parts.next()? != "ecr" {
+        return None;
+    }
+    let region = parts.next()?;
+    if !parts.next()?.starts_with("amazonaws") {
+        return None;
+    }
+    Some((account_id.to_string(), region.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ecr_registry_accepts_ecr_hosts() {
+        assert_eq!(
+            parse_ecr_registry("https://123456789012.dkr.ecr.us-east-1.amazonaws.com"),
+            Some(("123456789012".to_string(), "us-east-1".to_string()))
+        );
+        assert_eq!(
+            parse_ecr_registry("123456789012.dkr.ecr.cn-north-1.amazonaws.com.cn/repo"),
+            Some(("123456789012".to_string(), "cn-north-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_ecr_registry_rejects_other_registries() {
+        assert_eq!(parse_ecr_registry("https://index.docker.io/v1/"), None);
+        assert_eq!(parse_ecr_registry("ghcr.io"), None);
+    }
+}
@@ -1,66 +1,160 @@
-use anyhow::{Context, Result};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Context, Result};
 use bollard::auth::DockerCredentials;
 use log::info;
-use std::path::Path;
-use xshell::{cmd, Shell};
+use xshell::{cmd, Cmd, Shell};
+
+/// Distinguishes temporary images built in the same second by concurrent builds
+static TEMP_IMAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Run a command, echoing it like xshell does, with its stdout sent to stderr
+///
+/// Docker prints build and push progress on stdout; keeping it on stderr leaves
+/// stdout to the command's own output, such as the `--output json` manifest.
+fn run_to_stderr(cmd: Cmd<'_>) -> Result<()> {
+    let display = cmd.to_string();
+    eprintln!("$ {}", display);
+    let status = Command::from(cmd)
+        .stdout(io::stderr())
+        .status()
+        .with_context(|| format!("failed to run `{}`", display))?;
+    if !status.success() {
+        return Err(anyhow!("command exited with {}: `{}`", status, display));
+    }
+    Ok(())
+}
+
+/// Run a long docker command like [`run_to_stderr`], killing it if the returned
+/// future is dropped
+///
+/// `template build --watch` abandons local builds and pulls when files change;
+/// killing the process stops docker instead of leaving it running next to the
+/// rebuild.
+async fn run_killable(cmd: Cmd<'_>) -> Result<()> {
+    let display = cmd.to_string();
+    eprintln!("$ {}", display);
+    let status = tokio::process::Command::from(Command::from(cmd))
+        .stdout(io::stderr())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .with_context(|| format!("failed to run `{}`", display))?;
+    if !status.success() {
+        return Err(anyhow!("command exited with {}: `{}`", status, display));
+    }
+    Ok(())
+}
+
+/// Run docker commands on the blocking thread pool so concurrent builds can overlap
+async fn run_docker<T: Send + 'static>(
+    f: impl FnOnce(&Shell) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(move || {
+        let sh = Shell::new().context("failed to create shell")?;
+        f(&sh)
+    })
+    .await
+    .context("docker command task panicked")?
+}
 
-/// Build a temporary image to upload
-pub async fn build_temp_image(dockerfile_path: &Path) -> Result<String> {
-    let tag = format!("aws-e2b-temp:{}", chrono::Utc::now().timestamp());
+/// Build a temporary image to upload, with the given image labels
+pub async fn build_temp_image(
+    dockerfile_path: &Path,
+    labels: &[(String, String)],
+) -> Result<String> {
+    let tag = format!(
+        "aws-e2b-temp:{}-{}",
+        chrono::Utc::now().timestamp(),
+        TEMP_IMAGE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
     info!("Building temporary image: {}", tag);
-    let sh = Shell::new().context("failed to create shell")?;
     let context_dir = dockerfile_path
         .parent()
         .filter(|p| !p.as_os_str().is_empty())
         .unwrap_or_else(|| Path::new("."));
+    let image = &tag;
+    let label_args: Vec<String> = labels
+        .iter()
+        .flat_map(|(key, value)| ["--label".to_string(), format!("{}={}", key, value)])
+        .collect();
+    let sh = Shell::new().context("failed to create shell")?;
     // e2b does not support ARM, so force linux/amd64
-    cmd!(
+    run_killable(cmd!(
         sh,
-        "docker build --platform linux/amd64 -t {tag} -f {dockerfile_path} {context_dir}"
-    )
-    .run()?;
+        "docker build --platform linux/amd64 -t {image} {label_args...} -f {dockerfile_path} {context_dir}"
+    ))
+    .await?;
     Ok(tag)
 }
 
 /// Pull an image through the docker command-line interface with optional credentials
 pub async fn pull_docker_image(image: &str, creds: Option<&DockerCredentials>) -> Result<()> {
     info!("Pulling image: {}", image);
-    let sh = Shell::new().context("failed to create shell")?;
-    if let Some(c) = creds {
-        if let (Some(user), Some(pass), Some(server)) = (
-            c.username.as_ref(),
-            c.password.as_ref(),
-            c.serveraddress.as_ref(),
-        ) {
-            cmd!(sh, "docker login {server} -u {user} --password-stdin")
-                .stdin(pass)
-                .run()?;
-        }
+    if let Some(creds) = creds.cloned() {
+        run_docker(move |sh| docker_login(sh, &creds)).await?;
     }
-    cmd!(sh, "docker pull {image}").run()?;
-    Ok(())
+    let sh = Shell::new().context("failed to create shell")?;
+    run_killable(cmd!(sh, "docker pull {image}")).await
 }
 
 /// Tag an image
 pub async fn tag_image(source: &str, target: &str) -> Result<()> {
-    let sh = Shell::new().context("failed to create shell")?;
-    cmd!(sh, "docker tag {source} {target}").run()?;
-    Ok(())
+    let (source, target) = (source.to_string(), target.to_string());
+    run_docker(move |sh| run_to_stderr(cmd!(sh, "docker tag {source} {target}"))).await
 }
 
 /// Push an image to a remote registry
 pub async fn push_image(target: &str, creds: &DockerCredentials) -> Result<()> {
     info!("Pushing image: {}", target);
-    let sh = Shell::new().context("failed to create shell")?;
+    let target = target.to_string();
+    let creds = creds.clone();
+    run_docker(move |sh| {
+        docker_login(sh, &creds)?;
+        run_to_stderr(cmd!(sh, "docker push {target}"))
+    })
+    .await
+}
+
+/// Digest of an image in its registry, looked up without pulling the image
+///
+/// Uses the registry credentials docker already has.
+pub async fn remote_image_digest(image: &str) -> Result<String> {
+    let image = image.to_string();
+    let format = "{{json .Manifest}}";
+    run_docker(move |sh| {
+        let manifest = cmd!(
+            sh,
+            "docker buildx imagetools inspect --format {format} {image}"
+        )
+        .quiet()
+        .ignore_stderr()
+        .read()?;
+        let manifest: serde_json::Value = serde_json::from_str(&manifest)
+            .with_context(|| format!("unexpected manifest output for {}", image))?;
+        manifest["digest"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("the manifest of {} has no digest", image))
+    })
+    .await
+}
+
+/// Log in to the registry named in `creds`, if it carries a username and password
+fn docker_login(sh: &Shell, creds: &DockerCredentials) -> Result<()> {
     if let (Some(user), Some(pass), Some(server)) = (
         creds.username.as_ref(),
         creds.password.as_ref(),
         creds.serveraddress.as_ref(),
     ) {
+        // Read rather than run so "Login Succeeded" does not reach stdout
         cmd!(sh, "docker login {server} -u {user} --password-stdin")
             .stdin(pass)
-            .run()?;
+            .quiet()
+            .read()?;
     }
-    cmd!(sh, "docker push {target}").run()?;
     Ok(())
 }
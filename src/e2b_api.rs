@@ -90,6 +90,89 @@ pub async fn notify_build_complete(
     Ok(())
 }
 
+/// Check that the access token is accepted by the e2b API of the given domain
+pub async fn verify_access_token(e2b_domain: &str, access_token: &str) -> Result<()> {
+    let url = format!("https://api.{}/templates", e2b_domain);
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_str(access_token)?);
+    let client = reqwest::Client::new();
+    let resp = client.get(&url).headers(headers).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "access token was rejected by {} HTTP {}: {}",
+            url,
+            status,
+            text
+        ));
+    }
+    Ok(())
+}
+
+/// A team the authenticated user belongs to
+#[derive(Debug, Clone, Deserialize)]
+pub struct Team {
+    #[serde(rename = "teamID")]
+    pub team_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "apiKey", default)]
+    pub api_key: Option<String>,
+    #[serde(rename = "isDefault", default)]
+    pub is_default: bool,
+}
+
+/// A template visible to the access token, as returned by `GET /templates`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateInfo {
+    #[serde(rename = "templateID")]
+    pub template_id: String,
+    /// Latest successful build of the template
+    #[serde(rename = "buildID", default)]
+    pub build_id: String,
+    #[serde(default)]
+    pub aliases: Option<Vec<String>>,
+    #[serde(rename = "cpuCount", default)]
+    pub cpu_count: Option<u32>,
+    #[serde(rename = "memoryMB", default)]
+    pub memory_mb: Option<u32>,
+}
+
+/// List the templates the access token can see
+pub async fn list_templates(e2b_domain: &str, access_token: &str) -> Result<Vec<TemplateInfo>> {
+    let url = format!("https://api.{}/templates", e2b_domain);
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_str(access_token)?);
+    let client = reqwest::Client::new();
+    let resp = client.get(&url).headers(headers).send().await?;
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(anyhow!(
+            "failed to list templates HTTP {}: {}",
+            status,
+            text
+        ));
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// List the teams of the user owning the access token
+pub async fn list_teams(e2b_domain: &str, access_token: &str) -> Result<Vec<Team>> {
+    let url = format!("https://api.{}/teams", e2b_domain);
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_str(access_token)?);
+    let client = reqwest::Client::new();
+    let resp = client.get(&url).headers(headers).send().await?;
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(anyhow!("failed to list teams HTTP {}: {}", status, text));
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
 #[derive(Deserialize)]
 struct StatusResp {
     status: String,
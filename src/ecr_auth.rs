@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use aws_sdk_ecr as ecr;
+use log::{info, warn};
+
+use crate::aws_utils::{get_ecr_auth, EcrAuth};
+use crate::config::user_cache_dir;
+
+/// Refresh the token when it expires within this many seconds
+const REFRESH_MARGIN_SECS: i64 = 15 * 60;
+
+/// Caching provider of ECR credentials shared by the pull and push steps
+///
+/// The token is fetched lazily, reused until it is close to expiry and then
+/// refreshed. When enabled, the token is also persisted under
+/// `~/.aws_e2b/cache` keyed by account and region so later runs can reuse it.
+pub struct EcrAuthProvider {
+    client: ecr::Client,
+    cache: Mutex<Option<EcrAuth>>,
+    disk_cache: Option<PathBuf>,
+}
+
+impl EcrAuthProvider {
+    /// Create a provider; `use_disk_cache` enables the on-disk token cache
+    pub fn new(client: ecr::Client, account_id: &str, region: &str, use_disk_cache: bool) -> Self {
+        let disk_cache = if use_disk_cache {
+            user_cache_dir().map(|d| d.join(format!("ecr-token-{}-{}.json", account_id, region)))
+        } else {
+            None
+        };
+        Self {
+            client,
+            cache: Mutex::new(None),
+            disk_cache,
+        }
+    }
+
+    /// Return valid credentials, refreshing them if they are missing or about to expire
+    pub async fn get(&self) -> Result<EcrAuth> {
+        let now = chrono::Utc::now().timestamp();
+        if let Some(auth) = self.cache.lock().unwrap().as_ref() {
+            if !needs_refresh(auth.expires_at, now) {
+                return Ok(auth.clone());
+            }
+        }
+
+        if let Some(auth) = self.read_disk_cache() {
+            if !needs_refresh(auth.expires_at, now) {
+                info!("Using cached ECR credentials");
+                *self.cache.lock().unwrap() = Some(auth.clone());
+                return Ok(auth);
+            }
+        }
+
+        info!("Fetching ECR credentials");
+        let auth = get_ecr_auth(&self.client).await?;
+        if let Err(err) = self.write_disk_cache(&auth) {
+            warn!("Failed to write ECR credential cache: {:#}", err);
+        }
+        *self.cache.lock().unwrap() = Some(auth.clone());
+        Ok(auth)
+    }
+
+    fn read_disk_cache(&self) -> Option<EcrAuth> {
+        let path = self.disk_cache.as_ref()?;
+        let raw = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    fn write_disk_cache(&self, auth: &EcrAuth) -> Result<()> {
+        let Some(path) = self.disk_cache.as_ref() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create cache directory: {}", dir.display()))?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("failed to open cache file: {}", path.display()))?;
+        file.write_all(serde_json::to_string(auth)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Whether a token expiring at `expires_at` should be refreshed at time `now`
+fn needs_refresh(expires_at: i64, now: i64) -> bool {
+    expires_at - now <= REFRESH_MARGIN_SECS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_refresh_near_expiry() {
+        assert!(needs_refresh(1_000, 1_000));
+        assert!(needs_refresh(1_000 + REFRESH_MARGIN_SECS, 1_000));
+        assert!(!needs_refresh(1_000 + REFRESH_MARGIN_SECS + 1, 1_000));
+    }
+}
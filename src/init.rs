@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_sts as sts;
+use log::info;
+use toml_edit::value;
+
+use crate::args::{ConfigInitArgs, TemplateInitArgs};
+use crate::aws_utils::{fetch_aws_account_id, load_aws_config};
+use crate::build::{format_bearer_token, DEFAULT_CPU_COUNT, DEFAULT_IMAGE, DEFAULT_MEMORY_MB};
+use crate::config::{
+    active_profile_name, read_raw_user_config, read_user_config_document, user_section_mut,
+    write_user_config_document, ProfileSection,
+};
+use crate::e2b_api::verify_access_token;
+use crate::secrets::resolve_secret;
+
+/// Handle `config init`: collect cluster settings and write them to the user configuration
+///
+/// Values passed as flags are used as-is; missing ones are prompted for unless
+/// `--non-interactive` is set. Existing values are offered as defaults and other
+/// keys in the file are left untouched.
+pub async fn run_config_init(args: ConfigInitArgs) -> Result<()> {
+    let profile = active_profile_name();
+    // Offer the values already stored in the target section as defaults
+    let raw = read_raw_user_config()?.unwrap_or_default();
+    let current = match profile.as_deref() {
+        Some(name) => raw
+            .profiles
+            .and_then(|mut p| p.remove(name))
+            .unwrap_or_default(),
+        None => ProfileSection {
+            aws: raw.aws,
+            e2b: raw.e2b,
+            replicas: None,
+        },
+    };
+    let current_aws = current.aws.unwrap_or_default();
+    let current_e2b = current.e2b.unwrap_or_default();
+
+    let ask =
+        |label: &str, flag: Option<String>, existing: Option<String>| -> Result<Option<String>> {
+            if flag.is_some() || args.non_interactive {
+                return Ok(flag.or(existing));
+            }
+            prompt(label, existing.as_deref())
+        };
+    // Secrets are typed without echo and stored values are not shown
+    let ask_secret =
+        |label: &str, flag: Option<String>, existing: Option<String>| -> Result<Option<String>> {
+            if flag.is_some() || args.non_interactive {
+                return Ok(flag.or(existing));
+            }
+            prompt_secret(label, existing)
+        };
+    let aws_region = ask(
+        "AWS region",
+        args.aws_region.clone(),
+        current_aws.aws_region,
+    )?
+    .ok_or_else(|| anyhow!("An AWS region is required (--aws-region)"))?;
+    let e2b_domain = ask(
+        "e2b domain",
+        args.e2b_domain.clone(),
+        current_e2b.e2b_domain,
+    )?
+    .ok_or_else(|| anyhow!("An e2b domain is required (--e2b-domain)"))?;
+    let access_token = ask_secret(
+        "e2b access token",
+        args.e2b_access_token.clone(),
+        current_e2b.e2b_access_token,
+    )?;
+    let team_id = ask(
+        "e2b team ID",
+        args.e2b_team_id.clone(),
+        current_e2b.e2b_team_id,
+    )?;
+    let api_key = ask_secret(
+        "e2b API key",
+        args.e2b_api_key.clone(),
+        current_e2b.e2b_api_key,
+    )?;
+
+    if !args.no_verify {
+        let shared_config = load_aws_config(&aws_region).await;
+        let account_id = fetch_aws_account_id(&sts::Client::new(&shared_config))
+            .await
+            .with_context(|| format!("failed to verify AWS credentials in {}", aws_region))?;
+        info!(
+            "AWS credentials valid for account {} in {}",
+            account_id, aws_region
+        );
+        if let Some(ref token) = access_token {
+            let token = resolve_secret(token, Some(&aws_region)).await?;
+            verify_access_token(&e2b_domain, &format_bearer_token(&token)).await?;
+            info!("e2b access token accepted by {}", e2b_domain);
+        }
+    }
+
+    let mut doc = read_user_config_document()?;
+    user_section_mut(&mut doc, profile.as_deref(), "aws")?["aws_region"] = value(aws_region);
+    let e2b = user_section_mut(&mut doc, profile.as_deref(), "e2b")?;
+    e2b["e2b_domain"] = value(e2b_domain);
+    for (key, val) in [
+        ("e2b_access_token", access_token),
+        ("e2b_team_id", team_id),
+        ("e2b_api_key", api_key),
+    ] {
+        if let Some(val) = val {
+            e2b[key] = value(val);
+        }
+    }
+    let path = write_user_config_document(&doc)?;
+    match profile {
+        Some(name) => info!("Wrote profile {} to {}", name, path.display()),
+        None => info!("Wrote {}", path.display()),
+    }
+    Ok(())
+}
+
+/// Ask for a value on stdin, returning `default` when the answer is empty
+pub fn prompt(label: &str, default: Option<&str>) -> Result<Option<String>> {
+    match default {
+        Some(d) => ask_line(
+            &format!("{} [{}]: ", label, d),
+            false,
+            default.map(str::to_string),
+        ),
+        None => ask_line(&format!("{}: ", label), false, None),
+    }
+}
+
+/// Ask for a secret without echoing it, returning `stored` when the answer is empty
+///
+/// A stored value is only marked as present, never printed.
+pub fn prompt_secret(label: &str, stored: Option<String>) -> Result<Option<String>> {
+    let marker = if stored.is_some() { " [stored]" } else { "" };
+    ask_line(&format!("{}{}: ", label, marker), true, stored)
+}
+
+fn ask_line(question: &str, hidden: bool, default: Option<String>) -> Result<Option<String>> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{}", question)?;
+    stderr.flush()?;
+    let line = if hidden {
+        read_hidden_line()?
+    } else {
+        read_line()?
+    };
+    let answer = line.trim();
+    if answer.is_empty() {
+        Ok(default)
+    } else {
+        Ok(Some(answer.to_string()))
+    }
+}
+
+fn read_line() -> Result<String> {
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("failed to read from stdin")?;
+    Ok(line)
+}
+
+/// Read a line with terminal echo turned off; input that is not a terminal is read as-is
+fn read_hidden_line() -> Result<String> {
+    #[cfg(unix)]
+    {
+        let fd = libc::STDIN_FILENO;
+        // SAFETY: termios is plain data and is only used after tcgetattr filled it in
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: both calls only read the terminal state of a valid descriptor
+        if unsafe { libc::isatty(fd) } == 1 && unsafe { libc::tcgetattr(fd, &mut original) } == 0 {
+            let mut hidden = original;
+            hidden.c_lflag &= !libc::ECHO;
+            // Still echo the newline so the next output starts on its own line
+            hidden.c_lflag |= libc::ECHONL;
+            // SAFETY: `hidden` is a copy of the state tcgetattr returned
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) };
+            let line = read_line();
+            // SAFETY: restores the state read above
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+            return line;
+        }
+    }
+    read_line()
+}
+
+/// Handle `template init`: scaffold `aws_e2b.toml` and a starter Dockerfile
+pub fn run_template_init(args: TemplateInitArgs) -> Result<()> {
+    let dir = args.dir.clone().unwrap_or_else(|| ".".into());
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create directory: {}", dir.display()))?;
+    let config_path = dir.join("aws_e2b.toml");
+    let dockerfile_path = dir.join("Dockerfile");
+    for path in [&config_path, &dockerfile_path] {
+        if path.exists() && !args.force {
+            return Err(anyhow!(
+                "{} already exists; use --force to overwrite it",
+                path.display()
+            ));
+        }
+    }
+
+    write_scaffold(&config_path, &render_template_toml(&args))?;
+    write_scaffold(
+        &dockerfile_path,
+        &format!(
+            "FROM {}\n\n# Install additional packages and copy files needed by the sandbox here\n",
+            args.base_image.as_deref().unwrap_or(DEFAULT_IMAGE)
+        ),
+    )?;
+    info!(
+        "Created {} and {}",
+        config_path.display(),
+        dockerfile_path.display()
+    );
+    Ok(())
+}
+
+fn write_scaffold(path: &Path, content: &str) -> Result<()> {
+    fs::write(path, content).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Render the scaffolded `aws_e2b.toml`, commenting out settings that were not given
+fn render_template_toml(args: &TemplateInitArgs) -> String {
+    let optional = |key: &str, val: &Option<String>, example: &str| match val {
+        Some(v) => format!("{} = {}\n", key, value(v.as_str())),
+        None => format!("# {} = \"{}\"\n", key, example),
+    };
+    let mut out = String::from("[e2b]\n");
+    out.push_str(&format!(
+        "memory_mb = {}\n",
+        args.memory_mb.unwrap_or(DEFAULT_MEMORY_MB)
+    ));
+    out.push_str(&format!(
+        "cpu_count = {}\n",
+        args.cpu_count.unwrap_or(DEFAULT_CPU_COUNT)
+    ));
+    out.push_str(&optional("start_cmd", &args.start_cmd, "/root/start.sh"));
+    out.push_str(&optional(
+        "ready_cmd",
+        &args.ready_cmd,
+        "curl -sf http://127.0.0.1:8888/health",
+    ));
+    out.push_str(&optional("alias", &args.alias, "my-template"));
+    out.push_str("\n[docker]\ndockerfile = \"./Dockerfile\"\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template_toml_parses_as_config() {
+        let args = TemplateInitArgs {
+            dir: None,
+            alias: Some("python \"ci\"".to_string()),
+            memory_mb: Some(2048),
+            cpu_count: None,
+            start_cmd: None,
+            ready_cmd: None,
+            base_image: None,
+            force: false,
+        };
+        let cfg: crate::config::E2bConfigToml =
+            toml::from_str(&render_template_toml(&args)).unwrap();
+        let e2b = cfg.e2b.unwrap();
+        assert_eq!(e2b.memory_mb, Some(2048));
+        assert_eq!(e2b.cpu_count, Some(DEFAULT_CPU_COUNT));
+        assert_eq!(e2b.alias.as_deref(), Some("python \"ci\""));
+        assert_eq!(e2b.start_cmd, None);
+        assert_eq!(
+            cfg.docker.unwrap().dockerfile.as_deref(),
+            Some("./Dockerfile")
+        );
+    }
+}
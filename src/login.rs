@@ -0,0 +1,197 @@
+use std::io::{self, Read};
+use std::{env, fs};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use toml_edit::value;
+use which::which;
+
+use crate::args::{LoginArgs, LogoutArgs};
+use crate::build::format_bearer_token;
+use crate::config::{
+    active_profile_name, read_user_config, read_user_config_document, user_section_mut,
+    write_user_config_document,
+};
+use crate::e2b_api::{list_teams, verify_access_token, Team};
+use crate::init::{prompt, prompt_secret};
+
+/// Credentials stored by the official e2b CLI in `~/.e2b/config.json`
+#[derive(Deserialize)]
+struct E2bCliConfig {
+    #[serde(rename = "accessToken")]
+    access_token: Option<String>,
+}
+
+/// Handle `login`: obtain an access token, verify it, pick a team and store the credentials
+pub async fn run_login(args: LoginArgs) -> Result<()> {
+    let profile = active_profile_name();
+    let e2b_domain = args
+        .e2b_domain
+        .clone()
+        .or_else(|| env::var("E2B_DOMAIN").ok())
+        // A profile being created by this login does not exist yet, so tolerate lookup errors
+        .or_else(|| {
+            read_user_config()
+                .ok()
+                .flatten()
+                .and_then(|c| c.e2b.and_then(|e| e.e2b_domain))
+        })
+        .ok_or_else(|| {
+            anyhow!("Missing e2b domain: pass --e2b-domain, set E2B_DOMAIN or configure [e2b].e2b_domain")
+        })?;
+
+    let raw_token = if args.browser {
+        browser_login(&e2b_domain)?
+    } else {
+        match args.token.clone() {
+            Some(token) => token,
+            None if args.token_stdin => {
+                let mut token = String::new();
+                io::stdin()
+                    .read_to_string(&mut token)
+                    .context("failed to read the access token from stdin")?;
+                Some(token.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .ok_or_else(|| anyhow!("No access token on stdin"))?
+            }
+            None => prompt_secret("e2b access token", None)?
+                .ok_or_else(|| anyhow!("No access token provided"))?,
+        }
+    };
+    let access_token = format_bearer_token(&raw_token);
+    verify_access_token(&e2b_domain, &access_token).await?;
+    info!("Access token accepted by {}", e2b_domain);
+
+    let team = match list_teams(&e2b_domain, &access_token).await {
+        Ok(teams) => choose_team(teams, args.team.as_deref())?,
+        Err(err) => {
+            warn!("Could not list teams: {:#}", err);
+            None
+        }
+    };
+
+    let mut doc = read_user_config_document()?;
+    let e2b = user_section_mut(&mut doc, profile.as_deref(), "e2b")?;
+    if args.e2b_domain.is_some() {
+        e2b["e2b_domain"] = value(e2b_domain.as_str());
+    }
+    e2b["e2b_access_token"] = value(raw_token.trim());
+    let team_id = team.as_ref().map(|t| t.team_id.clone()).or(args.team);
+    if let Some(team_id) = team_id {
+        e2b["e2b_team_id"] = value(team_id);
+    }
+    if let Some(api_key) = team.and_then(|t| t.api_key) {
+        e2b["e2b_api_key"] = value(api_key);
+    }
+    let path = write_user_config_document(&doc)?;
+    info!("Saved credentials to {}", path.display());
+    Ok(())
+}
+
+/// Handle `logout`: remove stored credentials from the active profile
+///
+/// Without a profile the top-level `[e2b]` section is cleared; other profiles are kept.
+pub fn run_logout(args: LogoutArgs) -> Result<()> {
+    let profile = active_profile_name();
+    let mut doc = read_user_config_document()?;
+    let e2b = user_section_mut(&mut doc, profile.as_deref(), "e2b")?;
+    let mut keys = vec!["e2b_access_token", "e2b_api_key"];
+    if args.team {
+        keys.push("e2b_team_id");
+    }
+    let removed = keys
+        .into_iter()
+        .filter(|key| e2b.remove(key).is_some())
+        .count();
+    if removed == 0 {
+        info!("No stored credentials to remove");
+        return Ok(());
+    }
+    let path = write_user_config_document(&doc)?;
+    match profile {
+        Some(name) => info!(
+            "Removed credentials of profile {} from {}",
+            name,
+            path.display()
+        ),
+        None => info!("Removed credentials from {}", path.display()),
+    }
+    Ok(())
+}
+
+/// Run the official e2b CLI browser login and read the token it stored
+fn browser_login(e2b_domain: &str) -> Result<String> {
+    if which("e2b").is_err() {
+        return Err(anyhow!(
+            "The e2b CLI was not found. Please install it by following https://e2b.dev/docs/cli"
+        ));
+    }
+    let status = std::process::Command::new("e2b")
+        .args(["auth", "login"])
+        .env("E2B_DOMAIN", e2b_domain)
+        .status()
+        .context("failed to execute e2b auth login")?;
+    if !status.success() {
+        return Err(anyhow!("e2b auth login failed"));
+    }
+    let home = env::var("HOME").context("HOME is not set")?;
+    let path = std::path::Path::new(&home).join(".e2b").join("config.json");
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read e2b CLI credentials: {}", path.display()))?;
+    let cfg: E2bCliConfig = serde_json::from_str(&raw)
+        .with_context(|| format!("failed to parse e2b CLI credentials: {}", path.display()))?;
+    cfg.access_token
+        .ok_or_else(|| anyhow!("e2b auth login did not store an access token"))
+}
+
+/// Pick the requested team, the only team, or prompt when several are available
+fn choose_team(teams: Vec<Team>, requested: Option<&str>) -> Result<Option<Team>> {
+    if let Some(id) = requested {
+        return teams
+            .into_iter()
+            .find(|t| t.team_id == id || t.name == id)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Team {} was not found for this access token", id));
+    }
+    if teams.len() <= 1 {
+        return Ok(teams.into_iter().next());
+    }
+    eprintln!("Available teams:");
+    for (i, team) in teams.iter().enumerate() {
+        let marker = if team.is_default { " (default)" } else { "" };
+        eprintln!("  {}) {} {}{}", i + 1, team.name, team.team_id, marker);
+    }
+    let default_index = teams.iter().position(|t| t.is_default).unwrap_or(0) + 1;
+    let answer = prompt("Team", Some(&default_index.to_string()))?.unwrap_or_default();
+    let index: usize = answer
+        .parse()
+        .ok()
+        .filter(|i| (1..=teams.len()).contains(i))
+        .ok_or_else(|| anyhow!("Invalid team selection: {}", answer))?;
+    Ok(teams.into_iter().nth(index - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn team(id: &str, name: &str) -> Team {
+        Team {
+            team_id: id.to_string(),
+            name: name.to_string(),
+            api_key: None,
+            is_default: false,
+        }
+    }
+
+    #[test]
+    fn choose_team_matches_id_or_name() {
+        let teams = vec![team("t1", "dev"), team("t2", "prod")];
+        let chosen = choose_team(teams.clone(), Some("prod")).unwrap().unwrap();
+        assert_eq!(chosen.team_id, "t2");
+        assert!(choose_team(teams, Some("missing")).is_err());
+        let only = choose_team(vec![team("t1", "dev")], None).unwrap().unwrap();
+        assert_eq!(only.team_id, "t1");
+    }
+}
@@ -1,18 +1,42 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use std::env;
 use which::which;
 
+mod alias;
 mod args;
 mod aws_utils;
 mod build;
+mod build_cache;
+mod ci;
 mod config;
+mod config_cmd;
+mod credential_helper;
 mod docker_utils;
 mod e2b_api;
+mod ecr_auth;
+mod git;
+mod init;
+mod login;
+mod promote;
+mod pull_through;
+mod replication;
+mod report;
+mod secrets;
+mod watch;
 
+use alias::{run_alias_command, run_template_rollback};
 use args::{AwsE2bCli, AwsE2bCommand, ListArgs, TemplateCommand};
 use build::run_template_build;
-use config::read_user_config;
+use config::{
+    read_user_config, set_profile_override, set_strict_permissions, set_user_config_override,
+    ClusterSettings, Sourced,
+};
+use config_cmd::run_config_command;
+use credential_helper::run_credential_helper;
+use init::run_template_init;
+use login::{run_login, run_logout};
+use promote::run_template_promote;
+use secrets::resolve_optional_secret;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -29,28 +53,46 @@ async fn main() -> Result<()> {
         })
         .init();
 
-    let cli = AwsE2bCli::parse();
+    let mut cli = AwsE2bCli::parse();
+    cli.hoist_sandbox_globals();
+    if let Some(profile) = cli.profile {
+        set_profile_override(profile);
+    }
+    if let Some(path) = cli.user_config {
+        set_user_config_override(path);
+    }
+    if cli.strict {
+        set_strict_permissions();
+    }
 
     match cli.command {
         AwsE2bCommand::Template { command } => match command {
             TemplateCommand::Build(build_args) => run_template_build(build_args).await,
             TemplateCommand::List(list_args) => {
-                run_template_list(list_args)?;
+                run_template_list(list_args).await?;
                 Ok(())
             }
+            TemplateCommand::Init(init_args) => run_template_init(init_args),
+            TemplateCommand::Promote(promote_args) => run_template_promote(promote_args).await,
+            TemplateCommand::Alias { command } => run_alias_command(command).await,
+            TemplateCommand::Rollback(rollback_args) => run_template_rollback(rollback_args).await,
         },
         AwsE2bCommand::Sandbox(sandbox_args) => {
             let forward_args = std::iter::once("sandbox".to_string())
                 .chain(sandbox_args.args.into_iter())
                 .collect::<Vec<String>>();
-            proxy_to_e2b(&forward_args)?;
+            proxy_to_e2b(&forward_args).await?;
             Ok(())
         }
+        AwsE2bCommand::Login(login_args) => run_login(login_args).await,
+        AwsE2bCommand::Logout(logout_args) => run_logout(logout_args),
+        AwsE2bCommand::Config { command } => run_config_command(command).await,
+        AwsE2bCommand::CredentialHelper(helper_args) => run_credential_helper(helper_args).await,
     }
 }
 
 /// Forward a command to the official e2b CLI and inject domain, access token, and API key environment variables
-fn proxy_to_e2b(args: &[String]) -> Result<()> {
+async fn proxy_to_e2b(args: &[String]) -> Result<()> {
     // Ensure the official e2b CLI is installed before forwarding the command.
     if which("e2b").is_err() {
         return Err(anyhow!(
@@ -58,7 +100,7 @@ fn proxy_to_e2b(args: &[String]) -> Result<()> {
         ));
     }
 
-    let (domain_opt, token_opt, api_key_opt) = resolve_e2b_env_vars();
+    let (domain_opt, token_opt, api_key_opt) = resolve_e2b_env_vars().await?;
 
     let mut command = std::process::Command::new("e2b");
     command.args(args);
@@ -80,13 +122,11 @@ fn proxy_to_e2b(args: &[String]) -> Result<()> {
 }
 
 /// Handle the `template list` subcommand
-fn run_template_list(args: ListArgs) -> Result<()> {
+async fn run_template_list(args: ListArgs) -> Result<()> {
     let team_id = if let Some(tid) = args.team {
         tid
     } else {
-        read_user_config()
-            .ok()
-            .flatten()
+        read_user_config()?
             .and_then(|c| c.e2b.and_then(|e| e.e2b_team_id))
             .ok_or_else(|| {
                 anyhow!("Missing team identifier, please use --team or set [e2b].e2b_team_id in the configuration")
@@ -98,26 +138,19 @@ fn run_template_list(args: ListArgs) -> Result<()> {
         "--team".to_string(),
         team_id,
     ];
-    proxy_to_e2b(&cmd_args)
+    proxy_to_e2b(&cmd_args).await
 }
 
 /// Resolve the e2b domain, access token, and API key from environment variables or user configuration
-fn resolve_e2b_env_vars() -> (Option<String>, Option<String>, Option<String>) {
-    let user_cfg = read_user_config().ok().flatten();
-    let domain = env::var("E2B_DOMAIN").ok().or_else(|| {
-        user_cfg
-            .as_ref()
-            .and_then(|c| c.e2b.as_ref().and_then(|e| e.e2b_domain.clone()))
-    });
-    let token = env::var("E2B_ACCESS_TOKEN").ok().or_else(|| {
-        user_cfg
-            .as_ref()
-            .and_then(|c| c.e2b.as_ref().and_then(|e| e.e2b_access_token.clone()))
-    });
-    let api_key = env::var("E2B_API_KEY").ok().or_else(|| {
-        user_cfg
-            .as_ref()
-            .and_then(|c| c.e2b.as_ref().and_then(|e| e.e2b_api_key.clone()))
-    });
-    (domain, token, api_key)
+///
+/// Token and API key values that reference a secret store are fetched here.
+async fn resolve_e2b_env_vars() -> Result<(Option<String>, Option<String>, Option<String>)> {
+    let settings = ClusterSettings::read()?;
+    let value = |s: &Option<Sourced<String>>| s.as_ref().map(|s| s.value.clone());
+    let aws_region = value(&settings.aws_region);
+    let token =
+        resolve_optional_secret(value(&settings.e2b_access_token), aws_region.as_deref()).await?;
+    let api_key =
+        resolve_optional_secret(value(&settings.e2b_api_key), aws_region.as_deref()).await?;
+    Ok((value(&settings.e2b_domain), token, api_key))
 }
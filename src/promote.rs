@@ -0,0 +1,295 @@
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_ecr as ecr;
+use aws_sdk_sts as sts;
+use log::{info, warn};
+
+use crate::args::PromoteArgs;
+use crate::aws_utils::{
+    add_image_tag, create_ecr_repo_if_needed, ecr_image_digest, fetch_aws_account_id,
+    load_aws_config, ECR_REPO_PREFIX,
+};
+use crate::build::{
+    format_bearer_token, lookup_current_build, ClusterBuild, DEFAULT_CPU_COUNT, DEFAULT_MEMORY_MB,
+};
+use crate::config::{
+    load_e2b_toml, read_profile_config, validate_resources, E2bConfigToml, ResourceLimits,
+};
+use crate::docker_utils::{pull_docker_image, push_image, tag_image};
+use crate::e2b_api::build_template;
+use crate::ecr_auth::EcrAuthProvider;
+use crate::secrets::resolve_secret;
+
+/// ECR tag prefix recording the build a promoted image was copied from
+pub const PROMOTED_TAG_PREFIX: &str = "promoted-from-";
+
+/// An e2b cluster and ECR registry selected by a `[profiles.<name>]` table
+///
+/// Only the profile is consulted: `E2B_DOMAIN`, `E2B_ACCESS_TOKEN` and
+/// `AWS_REGION` would apply to both sides of a promotion, so they are ignored.
+struct Environment {
+    profile: String,
+    aws_region: String,
+    e2b_domain: String,
+    access_token: String,
+    limits: ResourceLimits,
+    ecr_client: ecr::Client,
+    ecr_auth: EcrAuthProvider,
+}
+
+impl Environment {
+    async fn connect(profile: &str) -> Result<Self> {
+        let user_cfg = read_profile_config(profile)?;
+        let missing = |key: &str| {
+            anyhow!(
+                "Missing {} for profile `{}`: configure it in [profiles.{}] of ~/.aws_e2b/config.toml",
+                key,
+                profile,
+                profile
+            )
+        };
+        let aws = user_cfg.aws.clone().unwrap_or_default();
+        let e2b = user_cfg.e2b.clone().unwrap_or_default();
+        let aws_region = aws.aws_region.ok_or_else(|| missing("aws.aws_region"))?;
+        let e2b_domain = e2b.e2b_domain.ok_or_else(|| missing("e2b.e2b_domain"))?;
+        let raw_token = e2b
+            .e2b_access_token
+            .ok_or_else(|| missing("e2b.e2b_access_token"))?;
+        let access_token =
+            format_bearer_token(&resolve_secret(&raw_token, Some(&aws_region)).await?);
+
+        let shared_config = load_aws_config(&aws_region).await;
+        let account_id = fetch_aws_account_id(&sts::Client::new(&shared_config)).await?;
+        let ecr_client = ecr::Client::new(&shared_config);
+        let ecr_auth = EcrAuthProvider::new(
+            ecr_client.clone(),
+            &account_id,
+            &aws_region,
+            aws.ecr_token_cache.unwrap_or(false),
+        );
+        info!(
+            "Profile `{}`: {} in {} (account {})",
+            profile, e2b_domain, aws_region, account_id
+        );
+        Ok(Self {
+            profile: profile.to_string(),
+            aws_region,
+            e2b_domain,
+            access_token,
+            limits: ResourceLimits::from_user_config(Some(&user_cfg)),
+            ecr_client,
+            ecr_auth,
+        })
+    }
+}
+
+/// Core logic for the `template promote` subcommand
+pub async fn run_template_promote(args: PromoteArgs) -> Result<()> {
+    let (source_profile, name) = parse_source(&args.from)?;
+    if source_profile == args.to {
+        return Err(anyhow!(
+            "--from and --to name the same profile `{}`",
+            args.to
+        ));
+    }
+    let (e2b_cfg, _) = load_e2b_toml(args.config_path.as_deref(), args.no_config)?;
+    let (cfg, is_table) = template_settings(&e2b_cfg, name)?;
+    let settings = cfg.e2b.unwrap_or_default();
+
+    let source = Environment::connect(source_profile).await?;
+    let target = Environment::connect(&args.to).await?;
+
+    // A table is found the way `template build` finds it; anything else is an ID or alias
+    let (template_id, alias) = if is_table {
+        (settings.template_id.clone(), settings.alias.clone())
+    } else {
+        (Some(name.to_string()), Some(name.to_string()))
+    };
+    let current = lookup_current_build(
+        &source.e2b_domain,
+        &source.access_token,
+        template_id.as_deref(),
+        alias.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "failed to look up template `{}` on {} (profile `{}`)",
+            name, source.e2b_domain, source.profile
+        )
+    })?
+    .ok_or_else(|| {
+        anyhow!(
+            "Template `{}` has no ready build on {} (profile `{}`)",
+            name,
+            source.e2b_domain,
+            source.profile
+        )
+    })?;
+
+    let source_repo = format!("{}/{}", ECR_REPO_PREFIX, current.template_id);
+    let digest = ecr_image_digest(&source.ecr_client, &source_repo, &current.build_id)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "Image {}:{} was not found in the ECR registry of profile `{}`",
+                source_repo,
+                current.build_id,
+                source.profile
+            )
+        })?;
+    let source_auth = source.ecr_auth.get().await?;
+    let source_image = format!(
+        "{}/{}@{}",
+        source_auth.registry.trim_start_matches("https://"),
+        source_repo,
+        digest
+    );
+    info!(
+        "Promoting template {} build {} from profile `{}` to `{}`: {}",
+        current.template_id, current.build_id, source.profile, target.profile, source_image
+    );
+
+    // Resources come from the source template; the API does not report its commands
+    let memory_mb = current
+        .memory_mb
+        .or(settings.memory_mb)
+        .unwrap_or(DEFAULT_MEMORY_MB);
+    let cpu_count = current
+        .cpu_count
+        .or(settings.cpu_count)
+        .unwrap_or(DEFAULT_CPU_COUNT);
+    validate_resources(Some(memory_mb), Some(cpu_count), &target.limits)?;
+    let alias = settings
+        .alias
+        .clone()
+        .or_else(|| current.aliases.iter().flatten().next().cloned());
+    let target_template_id = match args.template_id.clone() {
+        Some(tid) => Some(tid),
+        // A failed lookup must not turn into a second template with the same alias
+        None => lookup_current_build(
+            &target.e2b_domain,
+            &target.access_token,
+            None,
+            alias.as_deref(),
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "failed to look up the target template on {} (profile `{}`)",
+                target.e2b_domain, target.profile
+            )
+        })?
+        .map(|t| t.template_id),
+    };
+    match &target_template_id {
+        Some(tid) => info!("Updating template {} on {}", tid, target.e2b_domain),
+        None => info!("Creating template on {}", target.e2b_domain),
+    }
+
+    pull_docker_image(&source_image, Some(&source_auth.credentials)).await?;
+    let (build_id, template_id) = build_template(
+        &target.e2b_domain,
+        &target.access_token,
+        &format!("FROM {}", source_image),
+        memory_mb,
+        cpu_count,
+        settings.start_cmd,
+        settings.ready_cmd,
+        alias,
+        target_template_id,
+    )
+    .await?;
+    info!("buildID: {}", build_id);
+    info!("templateID: {}", template_id);
+
+    create_ecr_repo_if_needed(&target.ecr_client, &template_id).await?;
+    let target_auth = target.ecr_auth.get().await?;
+    let target_repo = format!("{}/{}", ECR_REPO_PREFIX, template_id);
+    let target_image = format!(
+        "{}/{}:{}",
+        target_auth.registry.trim_start_matches("https://"),
+        target_repo,
+        build_id
+    );
+    tag_image(&source_image, &target_image).await?;
+    push_image(&target_image, &target_auth.credentials).await?;
+    info!("Pushed promoted image to ECR: {}", target_image);
+    match ecr_image_digest(&target.ecr_client, &target_repo, &build_id).await {
+        Ok(Some(pushed)) if pushed != digest => warn!(
+            "Pushed image digest {} differs from the source digest {}",
+            pushed, digest
+        ),
+        Ok(_) => {}
+        Err(err) => warn!("Could not read the pushed image digest: {:#}", err),
+    }
+    if let Err(err) = add_image_tag(
+        &target.ecr_client,
+        &target_repo,
+        &build_id,
+        &format!("{}{}", PROMOTED_TAG_PREFIX, current.build_id),
+    )
+    .await
+    {
+        warn!("Could not record the source build: {:#}", err);
+    }
+
+    ClusterBuild {
+        aws_region: target.aws_region,
+        e2b_domain: target.e2b_domain,
+        access_token: target.access_token,
+        template_id: template_id.clone(),
+        build_id: build_id.clone(),
+    }
+    .finish()
+    .await?;
+    info!(
+        "Promoted build {} of template {} to template {} build {}",
+        current.build_id, current.template_id, template_id, build_id
+    );
+    Ok(())
+}
+
+/// Split `--from` into the profile and the template
+fn parse_source(from: &str) -> Result<(&str, &str)> {
+    match from.split_once(':') {
+        Some((profile, template)) if !profile.is_empty() && !template.is_empty() => {
+            Ok((profile, template))
+        }
+        _ => Err(anyhow!(
+            "--from must look like <profile>:<template>, got `{}`",
+            from
+        )),
+    }
+}
+
+/// Settings for `name` and whether it names a `[templates.<name>]` table
+///
+/// Other names only pick up the shared top-level settings.
+fn template_settings(e2b_cfg: &E2bConfigToml, name: &str) -> Result<(E2bConfigToml, bool)> {
+    if e2b_cfg.template_names().iter().any(|n| n == name) {
+        return Ok((e2b_cfg.resolve_template(name)?, true));
+    }
+    Ok((
+        E2bConfigToml {
+            templates: None,
+            ..e2b_cfg.clone()
+        },
+        false,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_source_requires_profile_and_template() {
+        assert_eq!(
+            parse_source("staging:python").unwrap(),
+            ("staging", "python")
+        );
+        assert!(parse_source("python").is_err());
+        assert!(parse_source(":python").is_err());
+        assert!(parse_source("staging:").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use aws_sdk_ecr as ecr;
+use aws_sdk_ecr::operation::create_pull_through_cache_rule::CreatePullThroughCacheRuleError;
+use aws_sdk_ecr::operation::describe_pull_through_cache_rules::DescribePullThroughCacheRulesError;
+use aws_sdk_ecr::types::UpstreamRegistry;
+use log::info;
+
+use crate::aws_utils::ecr_error;
+use crate::config::PullThroughCacheSection;
+
+/// Public registries that can be mirrored through an ECR pull-through cache rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Upstream {
+    DockerHub,
+    Ghcr,
+    Quay,
+}
+
+impl Upstream {
+    fn registry_url(self) -> &'static str {
+        match self {
+            Upstream::DockerHub => "registry-1.docker.io",
+            Upstream::Ghcr => "ghcr.io",
+            Upstream::Quay => "quay.io",
+        }
+    }
+
+    fn registry(self) -> UpstreamRegistry {
+        match self {
+            Upstream::DockerHub => UpstreamRegistry::DockerHub,
+            Upstream::Ghcr => UpstreamRegistry::GitHubContainerRegistry,
+            Upstream::Quay => UpstreamRegistry::Quay,
+        }
+    }
+
+    /// ECR repository prefix used for the cache rule
+    fn prefix(self) -> &'static str {
+        match self {
+            Upstream::DockerHub => "docker-hub",
+            Upstream::Ghcr => "ghcr",
+            Upstream::Quay => "quay",
+        }
+    }
+
+    /// Secrets Manager credential ARN; ECR requires one for Docker Hub and GHCR
+    fn credential_arn(self, settings: &PullThroughCacheSection) -> Result<Option<String>> {
+        let (arn, key) = match self {
+            Upstream::DockerHub => (
+                &settings.docker_hub_credential_arn,
+                "docker_hub_credential_arn",
+            ),
+            Upstream::Ghcr => (&settings.ghcr_credential_arn, "ghcr_credential_arn"),
+            Upstream::Quay => return Ok(None),
+        };
+        arn.clone().map(Some).ok_or_else(|| {
+            anyhow!(
+                "A pull-through cache rule for {} requires a Secrets Manager credential: configure [aws.pull_through_cache].{} in ~/.aws_e2b/config.toml",
+                self.registry_url(),
+                key
+            )
+        })
+    }
+}
+
+/// Rewrite a public image reference so it is pulled through an ECR pull-through cache,
+/// creating the cache rule if needed. Unsupported registries are returned unchanged.
+pub async fn resolve_pull_through_image(
+    ecr_client: &ecr::Client,
+    image: &str,
+    account_id: &str,
+    region: &str,
+    settings: &PullThroughCacheSection,
+) -> Result<String> {
+    let Some((upstream, path)) = parse_upstream_image(image) else {
+        info!(
+            "Image {} is not hosted on a supported public registry, pulling it directly",
+            image
+        );
+        return Ok(image.to_string());
+    };
+    ensure_pull_through_cache_rule(ecr_client, upstream, settings).await?;
+    let cached = format!(
+        "{}.dkr.ecr.{}.amazonaws.com/{}/{}",
+        account_id,
+        region,
+        upstream.prefix(),
+        path
+    );
+    info!("Using ECR pull-through cache: {} -> {}", image, cached);
+    Ok(cached)
+}
+
+/// Create the pull-through cache rule for `upstream` if it does not exist yet
+async fn ensure_pull_through_cache_rule(
+    ecr_client: &ecr::Client,
+    upstream: Upstream,
+    settings: &PullThroughCacheSection,
+) -> Result<()> {
+    let prefix = upstream.prefix();
+    match ecr_client
+        .describe_pull_through_cache_rules()
+        .ecr_repository_prefixes(prefix)
+        .send()
+        .await
+    {
+        Ok(_) => return Ok(()),
+        Err(err) => {
+            if !matches!(
+                err.as_service_error(),
+                Some(DescribePullThroughCacheRulesError::PullThroughCacheRuleNotFoundException(_))
+            ) {
+                return Err(ecr_error(
+                    &format!("describe pull-through cache rule {}", prefix),
+                    err,
+                ));
+            }
+        }
+    }
+
+    info!(
+        "Creating ECR pull-through cache rule: {} -> {}",
+        prefix,
+        upstream.registry_url()
+    );
+    let mut request = ecr_client
+        .create_pull_through_cache_rule()
+        .ecr_repository_prefix(prefix)
+        .upstream_registry_url(upstream.registry_url())
+        .upstream_registry(upstream.registry());
+    if let Some(arn) = upstream.credential_arn(settings)? {
+        request = request.credential_arn(arn);
+    }
+    match request.send().await {
+        Ok(_) => Ok(()),
+        Err(err)
+            if matches!(
+                err.as_service_error(),
+                Some(
+                    CreatePullThroughCacheRuleError::PullThroughCacheRuleAlreadyExistsException(_)
+                )
+            ) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(ecr_error(
+            &format!("create pull-through cache rule {}", prefix),
+            err,
+        )),
+    }
+}
+
+/// Split an image reference into its upstream registry and repository path
+/// (including tag or digest). Returns `None` for registries without cache support.
+fn parse_upstream_image(image: &str) -> Option<(Upstream, String)> {
+    let (first, rest) = match image.split_once('/') {
+        Some((first, rest))
+            if first.contains('.') || first.contains(':') || first == "localhost" =>
+        {
+            (Some(first), rest)
+        }
+        _ => (None, image),
+    };
+    let upstream = match first {
+        None | Some("docker.io") | Some("index.docker.io") | Some("registry-1.docker.io") => {
+            Upstream::DockerHub
+        }
+        Some("ghcr.io") => Upstream::Ghcr,
+        Some("quay.io") => Upstream::Quay,
+        Some(_) => return None,
+    };
+    // Official Docker Hub images live under the implicit `library/` namespace
+    let path = if upstream == Upstream::DockerHub && !rest.contains('/') {
+        format!("library/{}", rest)
+    } else {
+        rest.to_string()
+    };
+    Some((upstream, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_upstream_image_handles_docker_hub() {
+        assert_eq!(
+            parse_upstream_image("e2bdev/code-interpreter:latest"),
+            Some((
+                Upstream::DockerHub,
+                "e2bdev/code-interpreter:latest".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_upstream_image("ubuntu:22.04"),
+            Some((Upstream::DockerHub, "library/ubuntu:22.04".to_string()))
+        );
+        assert_eq!(
+            parse_upstream_image("docker.io/library/python:3.12"),
+            Some((Upstream::DockerHub, "library/python:3.12".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_upstream_image_handles_other_registries() {
+        assert_eq!(
+            parse_upstream_image("ghcr.io/org/image:v1"),
+            Some((Upstream::Ghcr, "org/image:v1".to_string()))
+        );
+        assert_eq!(
+            parse_upstream_image("quay.io/org/image@sha256:abc"),
+            Some((Upstream::Quay, "org/image@sha256:abc".to_string()))
+        );
+        assert_eq!(
+            parse_upstream_image("123456789012.dkr.ecr.us-east-1.amazonaws.com/img:tag"),
+            None
+        );
+    }
+}
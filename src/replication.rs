@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_ecr as ecr;
+use aws_sdk_ecr::types::{
+    ImageIdentifier, ReplicationConfiguration, ReplicationDestination, ReplicationRule,
+    ReplicationStatus, RepositoryFilter, RepositoryFilterType,
+};
+use log::info;
+
+use crate::aws_utils::{ecr_error, ECR_REPO_PREFIX};
+
+/// Give up waiting for ECR replication after this long
+const REPLICATION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Ensure the registry replicates template repositories to each of `regions`
+///
+/// Existing rules are preserved; a new rule is appended only for regions that
+/// are not yet covered by a rule matching the template repository prefix.
+pub async fn ensure_replication_rule(
+    ecr_client: &ecr::Client,
+    account_id: &str,
+    regions: &[String],
+) -> Result<()> {
+    let registry = ecr_client
+        .describe_registry()
+        .send()
+        .await
+        .map_err(|err| ecr_error("describe ECR registry", err))?;
+    let mut rules = registry
+        .replication_configuration()
+        .map(|c| c.rules.clone())
+        .unwrap_or_default();
+
+    let missing: Vec<&String> = regions
+        .iter()
+        .filter(|region| {
+            !rules
+                .iter()
+                .any(|rule| rule_covers(rule, region, account_id))
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "Adding ECR replication rule for {} to {}",
+        ECR_REPO_PREFIX,
+        missing
+            .iter()
+            .map(|r| r.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut rule = ReplicationRule::builder().repository_filters(
+        RepositoryFilter::builder()
+            .filter(ECR_REPO_PREFIX)
+            .filter_type(RepositoryFilterType::PrefixMatch)
+            .build()?,
+    );
+    for region in missing {
+        rule = rule.destinations(
+            ReplicationDestination::builder()
+                .region(region)
+                .registry_id(account_id)
+                .build()?,
+        );
+    }
+    rules.push(rule.build()?);
+    ecr_client
+        .put_replication_configuration()
+        .replication_configuration(
+            ReplicationConfiguration::builder()
+                .set_rules(Some(rules))
+                .build()?,
+        )
+        .send()
+        .await
+        .map_err(|err| ecr_error("update ECR replication configuration", err))?;
+    Ok(())
+}
+
+/// Wait until the image `repo_name:tag` has been replicated to `region`
+pub async fn wait_for_replication(
+    ecr_client: &ecr::Client,
+    repo_name: &str,
+    tag: &str,
+    region: &str,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    loop {
+        let resp = ecr_client
+            .describe_image_replication_status()
+            .repository_name(repo_name)
+            .image_id(ImageIdentifier::builder().image_tag(tag).build())
+            .send()
+            .await
+            .map_err(|err| ecr_error("describe image replication status", err))?;
+        let status = resp
+            .replication_statuses()
+            .iter()
+            .find(|s| s.region.as_deref() == Some(region));
+        match status.and_then(|s| s.status.as_ref()) {
+            Some(ReplicationStatus::Complete) => {
+                info!("Replicated {}:{} to {}", repo_name, tag, region);
+                return Ok(());
+            }
+            Some(ReplicationStatus::Failed) => {
+                return Err(anyhow!(
+                    "replication of {}:{} to {} failed: {}",
+                    repo_name,
+                    tag,
+                    region,
+                    status
+                        .and_then(|s| s.failure_code.as_deref())
+                        .unwrap_or("unknown reason")
+                ));
+            }
+            _ => {}
+        }
+        if started.elapsed() > REPLICATION_TIMEOUT {
+            return Err(anyhow!(
+                "timed out waiting for {}:{} to replicate to {}",
+                repo_name,
+                tag,
+                region
+            ));
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Whether `rule` replicates template repositories to `region` in the same account
+fn rule_covers(rule: &ReplicationRule, region: &str, account_id: &str) -> bool {
+    let has_destination = rule
+        .destinations
+        .iter()
+        .any(|d| d.region == region && d.registry_id == account_id);
+    let matches_repos = match rule.repository_filters.as_deref() {
+        None | Some([]) => true,
+        Some(filters) => filters.iter().any(|f| {
+            f.filter_type == RepositoryFilterType::PrefixMatch
+                && ECR_REPO_PREFIX.starts_with(f.filter.as_str())
+        }),
+    };
+    has_destination && matches_repos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(region: &str, filter: Option<&str>) -> ReplicationRule {
+        let mut builder = ReplicationRule::builder().destinations(
+            ReplicationDestination::builder()
+                .region(region)
+                .registry_id("123456789012")
+                .build()
+                .unwrap(),
+        );
+        if let Some(filter) = filter {
+            builder = builder.repository_filters(
+                RepositoryFilter::builder()
+                    .filter(filter)
+                    .filter_type(RepositoryFilterType::PrefixMatch)
+                    .build()
+                    .unwrap(),
+            );
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn rule_covers_matching_prefix() {
+        assert!(rule_covers(
+            &rule("eu-west-1", None),
+            "eu-west-1",
+            "123456789012"
+        ));
+        assert!(rule_covers(
+            &rule("eu-west-1", Some("e2bdev")),
+            "eu-west-1",
+            "123456789012"
+        ));
+    }
+
+    #[test]
+    fn rule_covers_rejects_other_regions_and_prefixes() {
+        assert!(!rule_covers(
+            &rule("eu-west-1", None),
+            "us-west-2",
+            "123456789012"
+        ));
+        assert!(!rule_covers(
+            &rule("eu-west-1", Some("other/")),
+            "eu-west-1",
+            "123456789012"
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::ci;
+use crate::git::GitMetadata;
+
+/// Final state of a template build
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildStatus {
+    Ready,
+    /// The current build already matched the inputs, so nothing was built
+    UpToDate,
+    #[default]
+    Failed,
+}
+
+impl BuildStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            BuildStatus::Ready => "ready",
+            BuildStatus::UpToDate => "up to date",
+            BuildStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Template settings the build was made with, after applying all sources
+#[derive(Debug, Default, Serialize)]
+pub struct BuildParameters {
+    pub memory_mb: u32,
+    pub cpu_count: u32,
+    pub start_cmd: Option<String>,
+    pub ready_cmd: Option<String>,
+    /// `dockerfile <path>`, `ecr-image <ref>` or `base image <ref>`
+    pub source: String,
+}
+
+/// Wall-clock duration of one step of the build
+#[derive(Debug, Serialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+}
+
+/// Outcome of the build on a replica cluster
+#[derive(Debug, Serialize)]
+pub struct ReplicaReport {
+    pub aws_region: String,
+    pub e2b_domain: String,
+    pub template_id: Option<String>,
+    pub build_id: Option<String>,
+    pub status: BuildStatus,
+    pub error: Option<String>,
+}
+
+/// Machine-readable record of one template build, filled in as the build progresses
+#[derive(Debug, Default, Serialize)]
+pub struct BuildReport {
+    /// `[templates.<name>]` the build was made from, if any
+    pub name: Option<String>,
+    pub status: BuildStatus,
+    pub error: Option<String>,
+    pub template_id: Option<String>,
+    pub alias: Option<String>,
+    pub build_id: Option<String>,
+    /// ECR reference the base image was pushed to
+    pub image: Option<String>,
+    pub image_digest: Option<String>,
+    /// Extra ECR tags added to the image besides the build ID
+    pub tags: Vec<String>,
+    pub fingerprint: Option<String>,
+    /// Commit the build was made from, unless `--no-git-metadata` was given
+    pub git: Option<GitMetadata>,
+    pub aws_account_id: Option<String>,
+    pub aws_region: Option<String>,
+    pub e2b_domain: Option<String>,
+    pub parameters: Option<BuildParameters>,
+    pub stages: Vec<StageTiming>,
+    pub replicas: Vec<ReplicaReport>,
+}
+
+impl BuildReport {
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Start timing a stage, inside a CI log group when enabled
+    pub fn begin(&self, stage: &'static str) -> StageTimer {
+        let title = match &self.name {
+            Some(name) => format!("{}: {}", name, stage),
+            None => stage.to_string(),
+        };
+        StageTimer {
+            stage,
+            started: Instant::now(),
+            _group: ci::group(&title),
+        }
+    }
+
+    /// Record how long a stage took; dropping the timer instead leaves it unrecorded
+    pub fn finish(&mut self, timer: StageTimer) {
+        self.stages.push(StageTiming {
+            stage: timer.stage.to_string(),
+            seconds: (timer.started.elapsed().as_secs_f64() * 1000.0).round() / 1000.0,
+        });
+    }
+
+    /// Mark the build as failed with `err`
+    pub fn fail(&mut self, err: &anyhow::Error) {
+        self.status = BuildStatus::Failed;
+        self.error = Some(format!("{:#}", err));
+    }
+}
+
+/// A running stage started with [`BuildReport::begin`]
+pub struct StageTimer {
+    stage: &'static str,
+    started: Instant,
+    _group: Option<ci::LogGroup>,
+}
+
+/// Manifest written by `--output json` and `--result-file`
+#[derive(Debug, Serialize)]
+pub struct BuildManifest<'a> {
+    pub templates: Vec<&'a BuildReport>,
+}
+
+impl BuildManifest<'_> {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize build results")
+    }
+
+    /// Write the manifest as JSON to `path`
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()? + "\n")
+            .with_context(|| format!("failed to write result file: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_serializes_status_in_snake_case() {
+        let mut report = BuildReport::new(Some("python".to_string()));
+        report.status = BuildStatus::UpToDate;
+        report.template_id = Some("tmpl".to_string());
+        let manifest = BuildManifest {
+            templates: vec![&report],
+        };
+        let json: serde_json::Value = serde_json::from_str(&manifest.to_json().unwrap()).unwrap();
+        assert_eq!(json["templates"][0]["status"], "up_to_date");
+        assert_eq!(json["templates"][0]["template_id"], "tmpl");
+        assert_eq!(json["templates"][0]["build_id"], serde_json::Value::Null);
+    }
+}
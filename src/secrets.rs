@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+use anyhow::{anyhow, Context, Result};
+use aws_config::SdkConfig;
+use aws_sdk_secretsmanager as secretsmanager;
+use aws_sdk_ssm as ssm;
+use xshell::{cmd, Shell};
+
+use crate::aws_utils::load_aws_config;
+
+/// A credential value that is either a literal or a reference to an external store
+#[derive(Debug, PartialEq, Eq)]
+enum SecretRef<'a> {
+    /// `aws-sm://secret-name#key`, where `#key` selects a field of a JSON secret
+    SecretsManager {
+        secret_id: &'a str,
+        key: Option<&'a str>,
+    },
+    /// `ssm:///path/param`, a SecureString or String parameter
+    Ssm {
+        name: &'a str,
+    },
+    /// `cmd://<command>`, whose trimmed stdout is the secret
+    Command {
+        command: &'a str,
+    },
+    /// `file://<path>`, whose trimmed content is the secret
+    File {
+        path: &'a str,
+    },
+    Literal(&'a str),
+}
+
+/// Whether a configured value refers to an external secret store
+pub fn is_secret_reference(value: &str) -> bool {
+    !matches!(parse_secret_ref(value), SecretRef::Literal(_))
+}
+
+/// Resolve a credential value, fetching it from its store if it is a reference
+///
+/// `aws_region` is used for Secrets Manager and SSM lookups unless the secret is
+/// given as a full ARN; without it the default AWS region chain applies.
+pub async fn resolve_secret(value: &str, aws_region: Option<&str>) -> Result<String> {
+    match parse_secret_ref(value) {
+        SecretRef::Literal(v) => Ok(v.to_string()),
+        SecretRef::SecretsManager { secret_id, key } => {
+            let region = arn_region(secret_id).or(aws_region);
+            let client = secretsmanager::Client::new(&secret_aws_config(region).await);
+            let resp = client
+                .get_secret_value()
+                .secret_id(secret_id)
+                .send()
+                .await
+                .with_context(|| {
+                    format!("failed to read secret {} from Secrets Manager", secret_id)
+                })?;
+            let secret = resp
+                .secret_string
+                .ok_or_else(|| anyhow!("secret {} has no string value", secret_id))?;
+            match key {
+                None => Ok(secret.trim().to_string()),
+                Some(key) => {
+                    let json: serde_json::Value = serde_json::from_str(&secret)
+                        .with_context(|| format!("secret {} is not a JSON object", secret_id))?;
+                    json.get(key)
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string)
+                        .ok_or_else(|| anyhow!("secret {} has no string key `{}`", secret_id, key))
+                }
+            }
+        }
+        SecretRef::Ssm { name } => {
+            let region = arn_region(name).or(aws_region);
+            let client = ssm::Client::new(&secret_aws_config(region).await);
+            let resp = client
+                .get_parameter()
+                .name(name)
+                .with_decryption(true)
+                .send()
+                .await
+                .with_context(|| format!("failed to read SSM parameter {}", name))?;
+            resp.parameter
+                .and_then(|p| p.value)
+                .ok_or_else(|| anyhow!("SSM parameter {} has no value", name))
+        }
+        SecretRef::Command { command } => {
+            let sh = Shell::new().context("failed to create shell")?;
+            let output = cmd!(sh, "sh -c {command}")
+                .quiet()
+                .read()
+                .with_context(|| format!("secret command failed: {}", command))?;
+            Ok(output.trim().to_string())
+        }
+        SecretRef::File { path } => {
+            let path = expand_home(path);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read secret file: {}", path.display()))?;
+            Ok(content.trim().to_string())
+        }
+    }
+}
+
+/// Resolve an optional credential value
+pub async fn resolve_optional_secret(
+    value: Option<String>,
+    aws_region: Option<&str>,
+) -> Result<Option<String>> {
+    match value {
+        Some(v) => resolve_secret(&v, aws_region).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_secret_ref(value: &str) -> SecretRef<'_> {
+    if let Some(rest) = value.strip_prefix("aws-sm://") {
+        let (secret_id, key) = match rest.rsplit_once('#') {
+            Some((id, key)) => (id, Some(key)),
+            None => (rest, None),
+        };
+        SecretRef::SecretsManager { secret_id, key }
+    } else if let Some(name) = value.strip_prefix("ssm://") {
+        SecretRef::Ssm { name }
+    } else if let Some(command) = value.strip_prefix("cmd://") {
+        SecretRef::Command { command }
+    } else if let Some(path) = value.strip_prefix("file://") {
+        SecretRef::File { path }
+    } else {
+        SecretRef::Literal(value)
+    }
+}
+
+/// Region embedded in an ARN such as `arn:aws:secretsmanager:us-east-1:...`
+fn arn_region(id: &str) -> Option<&str> {
+    let mut parts = id.strip_prefix("arn:")?.split(':');
+    parts.nth(2).filter(|r| !r.is_empty())
+}
+
+async fn secret_aws_config(region: Option<&str>) -> SdkConfig {
+    match region {
+        Some(region) => load_aws_config(region).await,
+        None => aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await,
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_secret_ref_recognizes_schemes() {
+        assert_eq!(
+            parse_secret_ref("aws-sm://e2b/ci#token"),
+            SecretRef::SecretsManager {
+                secret_id: "e2b/ci",
+                key: Some("token")
+            }
+        );
+        assert_eq!(
+            parse_secret_ref("ssm:///e2b/token"),
+            SecretRef::Ssm { name: "/e2b/token" }
+        );
+        assert_eq!(
+            parse_secret_ref("cmd://pass show e2b"),
+            SecretRef::Command {
+                command: "pass show e2b"
+            }
+        );
+        assert_eq!(parse_secret_ref("e2b_123"), SecretRef::Literal("e2b_123"));
+    }
+
+    #[test]
+    fn arn_region_extracts_region() {
+        assert_eq!(
+            arn_region("arn:aws:secretsmanager:eu-west-1:123456789012:secret:e2b"),
+            Some("eu-west-1")
+        );
+        assert_eq!(arn_region("e2b/ci"), None);
+    }
+
+    #[tokio::test]
+    async fn resolve_secret_reads_files_and_literals() {
+        let path = env::temp_dir().join(format!("aws_e2b_secret_{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+        let reference = format!("file://{}", path.display());
+        assert_eq!(resolve_secret(&reference, None).await.unwrap(), "from-file");
+        assert_eq!(resolve_secret("plain", None).await.unwrap(), "plain");
+        fs::remove_file(path).unwrap();
+    }
+}
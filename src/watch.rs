@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+
+use crate::args::BuildArgs;
+use crate::build::{build_templates, ImageSource, TemplateSettings};
+use crate::build_cache::context_files;
+use crate::ci;
+use crate::config::{find_e2b_toml, parse_e2b_toml_file, E2bConfigToml};
+
+/// How often the watched files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Quiet period after the last change before a rebuild starts
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Modification time and size of every watched file
+type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+/// Set while a build should stop at its next cancellation point
+static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Rebuild whenever the watched files change, until interrupted
+///
+/// A change while a build is running asks it to stop, which it does only in
+/// [`cancellable`] steps. A build that already exists on e2b is still pushed
+/// and notified, so no build is left waiting for an image.
+pub async fn watch_builds(args: &BuildArgs) -> Result<()> {
+    loop {
+        let before = snapshot(args);
+        info!("Watching {} files for changes", before.len());
+        CANCEL_REQUESTED.store(false, Ordering::SeqCst);
+        let build = build_templates(args);
+        tokio::pin!(build);
+        let finished = tokio::select! {
+            outcome = &mut build => Some(outcome),
+            () = wait_for_change(args, &before) => None,
+        };
+        match finished {
+            Some(outcome) => {
+                match outcome {
+                    Ok(()) => info!("Build finished; waiting for changes"),
+                    Err(err) => error!("Build failed: {:#}; waiting for changes", err),
+                }
+                wait_for_change(args, &before).await;
+            }
+            None => {
+                warn!("Files changed; stopping the running build at the next safe point");
+                CANCEL_REQUESTED.store(true, Ordering::SeqCst);
+                if let Err(err) = build.await {
+                    warn!("Build stopped: {:#}", err);
+                }
+            }
+        }
+        settle(args).await;
+        info!("Files changed; rebuilding");
+    }
+}
+
+/// Run a build step that `--watch` may abandon when the watched files change
+///
+/// Only steps that leave nothing half-done on e2b go through here: the local
+/// image build and pulls before a build is created, and the status poll after
+/// the build was notified. Docker commands in these steps are killed when the step is abandoned.
+pub async fn cancellable<T>(step: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::select! {
+        outcome = step => outcome,
+        () = wait_for_cancel() => Err(anyhow!("cancelled because the watched files changed")),
+    }
+}
+
+/// Whether the running build was asked to stop because the watched files changed
+pub fn cancel_requested() -> bool {
+    CANCEL_REQUESTED.load(Ordering::SeqCst)
+}
+
+async fn wait_for_cancel() {
+    while !cancel_requested() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn wait_for_change(args: &BuildArgs, before: &Snapshot) {
+    while snapshot(args) == *before {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Wait until the files stop changing for [`DEBOUNCE`]
+async fn settle(args: &BuildArgs) {
+    let mut last = snapshot(args);
+    loop {
+        tokio::time::sleep(DEBOUNCE).await;
+        let next = snapshot(args);
+        if next == last {
+            return;
+        }
+        last = next;
+    }
+}
+
+fn snapshot(args: &BuildArgs) -> Snapshot {
+    watched_files(args)
+        .into_iter()
+        .filter_map(|path| {
+            let meta = fs::metadata(&path).ok()?;
+            Some((path, (meta.modified().ok()?, meta.len())))
+        })
+        .collect()
+}
+
+/// `aws_e2b.toml`, the Dockerfiles of the selected templates and their build contexts
+///
+/// Context files excluded by `.dockerignore` and `.git` are not watched, so git
+/// reading the repository for build metadata does not restart the build. The configuration
+/// is read again each time, so edits to it change what is watched.
+fn watched_files(args: &BuildArgs) -> Vec<PathBuf> {
+    let config_path = match &args.config_path {
+        Some(path) => Some(path.clone()),
+        None if args.no_config => None,
+        None => std::env::current_dir()
+            .ok()
+            .and_then(|cwd| find_e2b_toml(&cwd)),
+    };
+    let e2b_cfg = config_path
+        .as_deref()
+        .and_then(|path| parse_e2b_toml_file(path).ok())
+        .unwrap_or_default();
+
+    let mut files: Vec<PathBuf> = config_path.iter().cloned().collect();
+    for dockerfile in dockerfiles(args, &e2b_cfg, config_path.as_deref()) {
+        let context_dir = dockerfile
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        files.extend(context_files(context_dir).unwrap_or_default());
+        files.push(dockerfile);
+    }
+    // Result files are rewritten by every build, so they must not trigger the next one
+    let dotenv = ci::dotenv_path(args.result_file.as_deref(), &|name| env::var(name).ok());
+    let outputs: Vec<PathBuf> = args
+        .result_file
+        .iter()
+        .chain([&dotenv])
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+    files.retain(|f| {
+        fs::canonicalize(f)
+            .map(|f| !outputs.contains(&f))
+            .unwrap_or(true)
+    });
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Dockerfiles the selected templates are built from
+fn dockerfiles(args: &BuildArgs, e2b_cfg: &E2bConfigToml, e2b_path: Option<&Path>) -> Vec<PathBuf> {
+    let names = match (&args.template, args.all) {
+        (Some(name), _) => vec![Some(name.as_str())],
+        (None, true) => e2b_cfg
+            .templates
+            .iter()
+            .flat_map(|t| t.keys())
+            .map(|name| Some(name.as_str()))
+            .collect(),
+        (None, false) => vec![None],
+    };
+    names
+        .into_iter()
+        .filter_map(|name| TemplateSettings::resolve(args, e2b_cfg, e2b_path, name).ok())
+        .filter_map(|settings| match settings.image.value {
+            ImageSource::Dockerfile(path) => Some(path),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn watched_files_follow_the_config_and_dockerignore() {
+        let dir = std::env::temp_dir().join(format!("aws_e2b_watch_{}", std::process::id()));
+        fs::create_dir_all(dir.join("app")).unwrap();
+        fs::write(
+            dir.join("aws_e2b.toml"),
+            "[templates.python.docker]\ndockerfile = \"app/Dockerfile\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("app/Dockerfile"), "FROM python:3.12\n").unwrap();
+        fs::write(dir.join("app/main.py"), "print()\n").unwrap();
+        fs::write(dir.join("app/debug.log"), "").unwrap();
+        fs::write(dir.join("app/.dockerignore"), "*.log\n").unwrap();
+        fs::create_dir_all(dir.join("app/.git")).unwrap();
+        fs::write(dir.join("app/.git/index"), "").unwrap();
+
+        let config = dir.join("aws_e2b.toml");
+        let args = BuildArgs::parse_from([
+            "build".as_ref(),
+            "--all".as_ref(),
+            "--config".as_ref(),
+            config.as_os_str(),
+        ]);
+        let files = watched_files(&args);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(files.contains(&config));
+        assert!(files.contains(&dir.join("app/Dockerfile")));
+        assert!(files.contains(&dir.join("app/main.py")));
+        assert!(!files.contains(&dir.join("app/debug.log")));
+        assert!(!files.contains(&dir.join("app/.git/index")));
+    }
+}